@@ -1,26 +1,589 @@
 use anyhow::{Result, Context};
 use serde::{Deserialize, Serialize};
+use std::fmt;
 use std::path::PathBuf;
 use std::fs;
 
+use crate::hardware::probe::{parse_pci_bus_id, Gpu};
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
     /// Fan curve points: (temperature_celsius, fan_speed_percent)
     pub curve: Vec<(u32, u32)>,
+    /// How the shared curve is drawn between its points
+    #[serde(default)]
+    pub interpolation: Interpolation,
+    /// Metric the shared curve's x axis stands for
+    #[serde(default)]
+    pub input: CurveInput,
+    /// More curves, each on its own input; the fans follow the highest of all curves
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub extra_curves: Vec<InputCurve>,
+    /// Temperature sources outside the GPU, for curve inputs
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sensors: Vec<SensorConfig>,
+    /// Follow the curves, or hold a target temperature
+    #[serde(default)]
+    pub mode: ControlMode,
+    /// Settings for `ControlMode::Pid`
+    #[serde(default)]
+    pub pid: PidConfig,
+    /// Settings for `ControlMode::Formula`
+    #[serde(default)]
+    pub formula: FormulaConfig,
     /// Daemon loop interval in milliseconds
     pub interval_ms: u64,
+    /// The speed only drops once the GPU is this many °C below the temperature that raised it
+    #[serde(default)]
+    pub hysteresis: u32,
+    /// Speed changes smaller than this many percent are not written
+    #[serde(default)]
+    pub deadband: u32,
+    /// Filter applied to the temperature before it reaches the curves
+    #[serde(default)]
+    pub smoothing: TempFilter,
+    /// Limits on how fast the fans may speed up and slow down
+    #[serde(default)]
+    pub ramp: RampConfig,
+    /// Raise the fans early when the temperature climbs or the load jumps
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub feed_forward: Option<FeedForwardConfig>,
+    /// Let the fans stop completely at low temperatures
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub zero_rpm: Option<ZeroRpmConfig>,
+    /// Leave the fans to the driver until the card gets hot
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hybrid: Option<HybridConfig>,
+    /// Per-GPU settings, keyed by UUID or PCI bus ID so they follow the card
+    #[serde(default)]
+    pub gpus: Vec<GpuConfig>,
+    /// What to do when the temperature sensor stops making sense
+    #[serde(default)]
+    pub fail_safe: FailSafeConfig,
+    /// Read the fans back and take control again when they stop following us
+    #[serde(default)]
+    pub verify: VerifyConfig,
+    /// Spot fans that stop or slow down on their own
+    #[serde(default)]
+    pub stall: StallConfig,
+    /// Escalating actions past critical temperatures, applied on top of any curve
+    #[serde(default = "default_critical")]
+    pub critical: Vec<CriticalStep>,
+    /// Virtual GPU used by `--simulate`
+    #[serde(default)]
+    pub simulation: SimulationConfig,
 }
 
-impl Default for Config {
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GpuConfig {
+    /// NVIDIA UUID, as shown by `nvidia-smi -L`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uuid: Option<String>,
+    /// PCI bus ID, e.g. "01:00.0" or "00000000:01:00.0"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pci_bus_id: Option<String>,
+    /// Curve for this GPU instead of the shared one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub curve: Option<Vec<(u32, u32)>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub interpolation: Option<Interpolation>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub input: Option<CurveInput>,
+    /// Extra curves for this GPU instead of the shared ones
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub extra_curves: Option<Vec<InputCurve>>,
+    /// Per-fan overrides
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub fans: Vec<FanConfig>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ControlMode {
+    /// Fan speed from the temperature curves
+    #[default]
+    Curve,
+    /// Fan speed from a PID controller holding `pid.target_temp`
+    Pid,
+    /// Fan speed from the user's `formula`, with the curves as fallback
+    Formula,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PidConfig {
+    /// Temperature to hold, in °C
+    pub target_temp: u32,
+    /// Percent of fan speed per °C above target
+    pub kp: f64,
+    /// Percent per °C·s of accumulated error
+    pub ki: f64,
+    /// Percent per °C/s of temperature change
+    pub kd: f64,
+    /// Output range in percent
+    pub min_speed: u32,
+    pub max_speed: u32,
+}
+
+impl Default for PidConfig {
+    fn default() -> Self {
+        Self {
+            target_temp: 68,
+            kp: 8.0,
+            ki: 0.6,
+            kd: 10.0,
+            min_speed: 20,
+            max_speed: 100,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FormulaConfig {
+    /// Expression returning a fan speed in percent, see `daemon::formula`
+    pub expression: String,
+    /// Evaluation steps allowed per tick
+    pub max_steps: u64,
+    /// Largest formula accepted, in syntax tree nodes
+    pub max_nodes: usize,
+    /// Wall-clock time allowed per evaluation
+    pub time_limit_ms: u64,
+}
+
+impl Default for FormulaConfig {
+    fn default() -> Self {
+        Self {
+            expression: String::new(),
+            max_steps: 10_000,
+            max_nodes: 1_000,
+            time_limit_ms: 5,
+        }
+    }
+}
+
+/// Shape of a curve between its points
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Interpolation {
+    /// Hold each point's speed until the next point
+    Step,
+    /// Straight lines between points
+    #[default]
+    Linear,
+    /// Smooth curve through the points that never overshoots them
+    MonotoneCubic,
+}
+
+/// Metric a curve is evaluated on
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CurveInput {
+    /// GPU core temperature in °C
+    #[default]
+    CoreTemp,
+    /// GPU utilization in percent
+    Usage,
+    /// Board power draw in watts
+    PowerDraw,
+    /// VRAM temperature in °C, only reported by some cards
+    MemoryTemp,
+    /// One of the `sensors`, by name
+    Sensor(String),
+}
+
+impl fmt::Display for CurveInput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CurveInput::CoreTemp => write!(f, "core temperature"),
+            CurveInput::Usage => write!(f, "GPU usage"),
+            CurveInput::PowerDraw => write!(f, "power draw"),
+            CurveInput::MemoryTemp => write!(f, "memory temperature"),
+            CurveInput::Sensor(name) => write!(f, "sensor {:?}", name),
+        }
+    }
+}
+
+impl CurveInput {
+    pub fn unit(&self) -> &'static str {
+        match self {
+            CurveInput::CoreTemp | CurveInput::MemoryTemp | CurveInput::Sensor(_) => "°C",
+            CurveInput::Usage => "%",
+            CurveInput::PowerDraw => "W",
+        }
+    }
+}
+
+/// A curve on its own input, combined with the main curve by taking the higher speed
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct InputCurve {
+    pub input: CurveInput,
+    /// Points: (input value, fan speed percent)
+    pub curve: Vec<(u32, u32)>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub interpolation: Option<Interpolation>,
+}
+
+/// A named temperature source that curves can use as their input
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SensorConfig {
+    pub name: String,
+    #[serde(flatten)]
+    pub source: SensorSource,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SensorSource {
+    /// CPU package temperature, picked from the sensors the system reports
+    Cpu,
+    /// Any system sensor whose label contains `label`, e.g. "nvme Composite"
+    Component { label: String },
+    /// A `/sys/class/hwmon` temperature, by chip name and sensor label or input name ("temp1")
+    Hwmon { chip: String, sensor: String },
+    /// First number in a file, multiplied by `scale`
+    File {
+        path: PathBuf,
+        #[serde(default = "default_scale")]
+        scale: f64,
+    },
+    /// Hottest of other sensors; "gpu" names the GPU's own core temperature
+    Max { sensors: Vec<String> },
+    Average { sensors: Vec<String> },
+    /// Sensor `a` minus sensor `b`
+    Difference { a: String, b: String },
+}
+
+fn default_scale() -> f64 {
+    1.0
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FanConfig {
+    /// Position of the fan on its GPU (0 = first fan)
+    pub index: u32,
+    /// Curve for this fan instead of the GPU's
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub curve: Option<Vec<(u32, u32)>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub interpolation: Option<Interpolation>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub input: Option<CurveInput>,
+    /// Added to the curve output, in percent (may be negative)
+    pub offset: i32,
+    /// Measured by `--calibrate`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub calibration: Option<FanCalibration>,
+}
+
+impl FanConfig {
+    /// Nothing overridden, the entry can be dropped
+    pub fn is_empty(&self) -> bool {
+        self.curve.is_none() && self.interpolation.is_none() && self.input.is_none() && self.offset == 0
+            && self.calibration.is_none()
+    }
+}
+
+/// How one fan actually responds to its duty cycle
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FanCalibration {
+    /// Lowest duty that gets the fan spinning from standstill and keeps it running
+    pub start_duty: u32,
+    /// Steady-state RPM at each measured duty: (Duty%, RPM), ascending
+    pub rpm: Vec<(u32, u32)>,
+}
+
+impl GpuConfig {
+    /// Human readable key, for logs
+    pub fn key(&self) -> String {
+        match (&self.uuid, &self.pci_bus_id) {
+            (Some(uuid), _) => uuid.clone(),
+            (None, Some(bus_id)) => format!("PCI {}", bus_id),
+            (None, None) => "<no uuid or pci_bus_id>".to_string(),
+        }
+    }
+
+    /// Whether this entry refers to the given GPU.
+    /// The UUID wins when both keys are set.
+    pub fn matches(&self, gpu: &Gpu) -> bool {
+        if let Some(uuid) = &self.uuid {
+            return gpu.uuid.as_deref().is_some_and(|u| u.eq_ignore_ascii_case(uuid));
+        }
+        if let Some(bus_id) = &self.pci_bus_id {
+            return match (parse_pci_bus_id(bus_id), gpu.pci_bus_id.as_deref().and_then(parse_pci_bus_id)) {
+                (Some(a), Some(b)) => a == b,
+                _ => false,
+            };
+        }
+        false
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TempFilter {
+    /// Use the raw reading
+    #[default]
+    None,
+    /// Exponential moving average; higher `alpha` follows the sensor more closely
+    Ema { alpha: f64 },
+    /// Plain average of the last `samples` readings
+    MovingAverage { samples: usize },
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RampConfig {
+    /// Fastest allowed speed-up in percent per second, unlimited if unset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub up: Option<f64>,
+    /// Fastest allowed slow-down in percent per second, unlimited if unset
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub down: Option<f64>,
+}
+
+/// Extra fan speed on top of the curve or PID output, decaying back to zero
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FeedForwardConfig {
+    /// Percent of boost per °C/s of temperature rise
+    pub slope_gain: f64,
+    /// Percent of boost per percent of utilization jump
+    pub usage_gain: f64,
+    /// Smallest rise in utilization between two readings that counts as a jump
+    pub usage_jump: u32,
+    /// Time constant of the decay back to the curve, in seconds
+    pub decay_secs: f64,
+    /// Upper bound on the boost, in percent
+    pub max_boost: u32,
+}
+
+impl Default for FeedForwardConfig {
+    fn default() -> Self {
+        Self {
+            slope_gain: 20.0,
+            usage_gain: 0.5,
+            usage_jump: 20,
+            decay_secs: 20.0,
+            max_boost: 40,
+        }
+    }
+}
+
+/// The fans stop at or below `stop_temp` and start again at `start_temp`.
+/// In between, and while running, the curve sets the speed as usual.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ZeroRpmConfig {
+    pub stop_temp: u32,
+    pub start_temp: u32,
+    /// Once started, keep the fans running at least this long
+    pub min_on_secs: u64,
+    /// Speed used briefly when starting from standstill, since many fans won't spin up lower
+    pub kick_speed: u32,
+    pub kick_secs: u64,
+}
+
+impl Default for ZeroRpmConfig {
+    fn default() -> Self {
+        Self {
+            stop_temp: 45,
+            start_temp: 55,
+            min_on_secs: 60,
+            kick_speed: 50,
+            kick_secs: 2,
+        }
+    }
+}
+
+/// The driver keeps the fans below `takeover_temp`; above it the curves do.
+/// Control goes back to the driver once the card is `hysteresis` °C below the takeover point.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HybridConfig {
+    pub takeover_temp: u32,
+    pub hysteresis: u32,
+}
+
+impl Default for HybridConfig {
+    fn default() -> Self {
+        Self {
+            takeover_temp: 75,
+            hysteresis: 5,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VerifyConfig {
+    pub enabled: bool,
+    /// Largest accepted difference between target and actual speed, in percent
+    pub tolerance: u32,
+    /// How long a fan may stay off target, e.g. while spinning up, before control is re-asserted
+    pub grace_secs: u64,
+}
+
+impl Default for VerifyConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            tolerance: 10,
+            grace_secs: 10,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StallConfig {
+    pub enabled: bool,
+    /// How long a fan may read too slow before it counts as failed
+    pub grace_secs: u64,
+    /// Duties below this may not turn a fan at all, so they aren't judged
+    pub min_duty: u32,
+    /// Slowest accepted RPM, as a fraction of what the fan has shown it does at the same duty
+    pub min_ratio: f64,
+    /// Send a desktop notification when a fan fails
+    pub notify: bool,
+}
+
+impl Default for StallConfig {
     fn default() -> Self {
         Self {
-            curve: vec![
-                (30, 0),
-                (50, 30),
-                (70, 60),
-                (85, 100),
+            enabled: true,
+            grace_secs: 15,
+            min_duty: 30,
+            min_ratio: 0.3,
+            notify: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FailSafeConfig {
+    /// Consecutive failed or implausible readings before the fail-safe engages
+    pub max_failures: u32,
+    /// Largest believable change between two readings, in °C
+    pub max_jump: u32,
+    pub action: FailSafeAction,
+    /// Fan speed used by the `safe_speed` action, in percent
+    pub safe_speed: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FailSafeAction {
+    /// Hold every fan at `safe_speed`
+    SafeSpeed,
+    /// Hand the fans back to the driver's automatic control
+    DriverAuto,
+}
+
+impl Default for FailSafeConfig {
+    fn default() -> Self {
+        Self {
+            max_failures: 3,
+            max_jump: 25,
+            action: FailSafeAction::SafeSpeed,
+            safe_speed: 100,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CriticalStep {
+    /// Takes effect at or above this temperature in °C
+    pub temp: u32,
+    /// Lowest fan speed allowed while the step is active, in percent
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fan_speed: Option<u32>,
+    /// Power limit in watts while the step is active, applied with `nvidia-smi -pl`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub power_limit: Option<u32>,
+    /// Send a desktop notification when the step is reached
+    pub notify: bool,
+    /// Shell command to run when the step is reached
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+}
+
+fn default_critical() -> Vec<CriticalStep> {
+    vec![
+        CriticalStep { temp: 88, fan_speed: Some(100), ..Default::default() },
+        CriticalStep { temp: 92, fan_speed: Some(100), notify: true, ..Default::default() },
+    ]
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SimulationConfig {
+    /// Number of virtual GPUs
+    pub gpus: u32,
+    /// Number of fans on each virtual GPU
+    pub fans_per_gpu: u32,
+    /// Room temperature the card cools towards
+    pub ambient_temp: f64,
+    /// Lowest fan speed the virtual card accepts, like real cards report
+    pub min_fan_speed: u32,
+    /// Load profile steps: (duration_seconds, gpu_usage_percent), repeated forever
+    pub load_profile: Vec<(u64, u32)>,
+}
+
+impl Default for SimulationConfig {
+    fn default() -> Self {
+        Self {
+            gpus: 1,
+            fans_per_gpu: 2,
+            ambient_temp: 30.0,
+            min_fan_speed: 0,
+            load_profile: vec![
+                (60, 5),
+                (120, 95),
+                (60, 40),
             ],
+        }
+    }
+}
+
+/// Shipped core temperature curve: (temperature_celsius, fan_speed_percent)
+pub const DEFAULT_CURVE: [(u32, u32); 4] = [
+    (30, 0),
+    (50, 30),
+    (70, 60),
+    (85, 100),
+];
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            curve: DEFAULT_CURVE.to_vec(),
+            interpolation: Interpolation::default(),
+            input: CurveInput::default(),
+            extra_curves: Vec::new(),
+            sensors: Vec::new(),
+            mode: ControlMode::default(),
+            pid: PidConfig::default(),
+            formula: FormulaConfig::default(),
             interval_ms: 2000,
+            hysteresis: 0,
+            deadband: 0,
+            smoothing: TempFilter::default(),
+            ramp: RampConfig::default(),
+            feed_forward: None,
+            zero_rpm: None,
+            hybrid: None,
+            gpus: Vec::new(),
+            fail_safe: FailSafeConfig::default(),
+            verify: VerifyConfig::default(),
+            stall: StallConfig::default(),
+            critical: default_critical(),
+            simulation: SimulationConfig::default(),
         }
     }
 }
@@ -74,21 +637,291 @@ impl Config {
         Ok(())
     }
 
-    /// Get curve as f64 array for GUI sliders (just speed values)
-    pub fn curve_speeds_f64(&self) -> [f64; 4] {
-        let mut speeds = [0.0; 4];
-        for (i, (_, speed)) in self.curve.iter().enumerate().take(4) {
-            speeds[i] = *speed as f64;
+    /// Settings for a detected GPU, if the config has an entry for it
+    pub fn gpu_config(&self, gpu: &Gpu) -> Option<&GpuConfig> {
+        self.gpus.iter().find(|entry| entry.matches(gpu))
+    }
+
+    /// Mutable settings for a detected GPU, if the config has an entry for it
+    pub fn gpu_config_mut(&mut self, gpu: &Gpu) -> Option<&mut GpuConfig> {
+        self.gpus.iter_mut().find(|entry| entry.matches(gpu))
+    }
+
+    /// Replace the curve a GPU follows: its own if it has one, otherwise the shared one
+    pub fn set_curve_for(&mut self, gpu: &Gpu, curve: Vec<(u32, u32)>) {
+        match self.gpu_config_mut(gpu).filter(|entry| entry.curve.is_some()) {
+            Some(entry) => entry.curve = Some(curve),
+            None => self.curve = curve,
+        }
+    }
+
+    /// Change the interpolation a GPU's curve uses, where `interpolation_for` reads it
+    pub fn set_interpolation_for(&mut self, gpu: &Gpu, interpolation: Interpolation) {
+        match self.gpu_config_mut(gpu).filter(|entry| entry.curve.is_some() || entry.interpolation.is_some()) {
+            Some(entry) => entry.interpolation = Some(interpolation),
+            None => self.interpolation = interpolation,
+        }
+    }
+
+    /// Curve to apply to a GPU: its own if configured, otherwise the shared one
+    pub fn curve_for(&self, gpu: &Gpu) -> &[(u32, u32)] {
+        self.gpu_config(gpu)
+            .and_then(|entry| entry.curve.as_deref())
+            .unwrap_or(&self.curve)
+    }
+
+    /// Interpolation for a GPU's curve, most specific setting first
+    pub fn interpolation_for(&self, gpu: &Gpu) -> Interpolation {
+        self.gpu_config(gpu)
+            .and_then(|entry| entry.interpolation)
+            .unwrap_or(self.interpolation)
+    }
+
+    /// Interpolation for one fan's curve, falling back to the GPU's
+    pub fn fan_interpolation_for(&self, gpu: &Gpu, index: usize) -> Interpolation {
+        self.fan_config(gpu, index)
+            .and_then(|fan| fan.interpolation)
+            .unwrap_or_else(|| self.interpolation_for(gpu))
+    }
+
+    /// Input of a GPU's curve, most specific setting first
+    pub fn input_for(&self, gpu: &Gpu) -> CurveInput {
+        self.gpu_config(gpu)
+            .and_then(|entry| entry.input.clone())
+            .unwrap_or_else(|| self.input.clone())
+    }
+
+    /// Input of one fan's curve, falling back to the GPU's
+    pub fn fan_input_for(&self, gpu: &Gpu, index: usize) -> CurveInput {
+        self.fan_config(gpu, index)
+            .and_then(|fan| fan.input.clone())
+            .unwrap_or_else(|| self.input_for(gpu))
+    }
+
+    /// Core temperature curve to stand in for a GPU curve whose input can't be read:
+    /// the GPU's own curve or the shared one if either runs on core temperature,
+    /// otherwise the default curve
+    pub fn core_temp_curve_for(&self, gpu: &Gpu) -> (&[(u32, u32)], Interpolation) {
+        if self.input_for(gpu) == CurveInput::CoreTemp {
+            (self.curve_for(gpu), self.interpolation_for(gpu))
+        } else if self.input == CurveInput::CoreTemp {
+            (&self.curve, self.interpolation)
+        } else {
+            (&DEFAULT_CURVE, Interpolation::default())
         }
-        speeds
     }
 
-    /// Update curve from GUI speed values
-    pub fn set_curve_speeds(&mut self, speeds: &[f64; 4]) {
-        let temps = [30, 50, 70, 85];
-        self.curve.clear();
-        for (i, &speed) in speeds.iter().enumerate() {
-            self.curve.push((temps[i], speed as u32));
+    /// Curves combined with every curve of a GPU: its own if configured, otherwise the shared ones
+    pub fn extra_curves_for(&self, gpu: &Gpu) -> &[InputCurve] {
+        self.gpu_config(gpu)
+            .and_then(|entry| entry.extra_curves.as_deref())
+            .unwrap_or(&self.extra_curves)
+    }
+
+    /// Overrides for the fan at `index` on a GPU
+    pub fn fan_config(&self, gpu: &Gpu, index: usize) -> Option<&FanConfig> {
+        self.gpu_config(gpu)?
+            .fans
+            .iter()
+            .find(|fan| fan.index as usize == index)
+    }
+
+    /// Measured response of a fan, if it was calibrated
+    pub fn fan_calibration(&self, gpu: &Gpu, index: usize) -> Option<&FanCalibration> {
+        self.fan_config(gpu, index)?.calibration.as_ref()
+    }
+
+    /// Curve to apply to one fan, falling back to the GPU's curve
+    pub fn fan_curve_for(&self, gpu: &Gpu, index: usize) -> &[(u32, u32)] {
+        self.fan_config(gpu, index)
+            .and_then(|fan| fan.curve.as_deref())
+            .unwrap_or_else(|| self.curve_for(gpu))
+    }
+
+    /// Mutable overrides for a fan, creating the GPU and fan entries as needed.
+    /// None if the GPU has neither a UUID nor a PCI bus ID to key the entry by.
+    pub fn fan_config_mut(&mut self, gpu: &Gpu, index: usize) -> Option<&mut FanConfig> {
+        let pos = match self.gpus.iter().position(|entry| entry.matches(gpu)) {
+            Some(pos) => pos,
+            None => {
+                if gpu.uuid.is_none() && gpu.pci_bus_id.is_none() {
+                    return None;
+                }
+                self.gpus.push(GpuConfig {
+                    uuid: gpu.uuid.clone(),
+                    pci_bus_id: if gpu.uuid.is_none() { gpu.pci_bus_id.clone() } else { None },
+                    ..Default::default()
+                });
+                self.gpus.len() - 1
+            }
+        };
+
+        let fans = &mut self.gpus[pos].fans;
+        let fan_pos = match fans.iter().position(|fan| fan.index as usize == index) {
+            Some(fan_pos) => fan_pos,
+            None => {
+                fans.push(FanConfig { index: index as u32, ..Default::default() });
+                fans.len() - 1
+            }
+        };
+        Some(&mut fans[fan_pos])
+    }
+
+    /// Drop fan and GPU entries that no longer override anything
+    pub fn prune(&mut self) {
+        for entry in &mut self.gpus {
+            entry.fans.retain(|fan| !fan.is_empty());
         }
+        self.gpus.retain(|entry| {
+            entry.curve.is_some()
+                || entry.interpolation.is_some()
+                || entry.input.is_some()
+                || entry.extra_curves.is_some()
+                || !entry.fans.is_empty()
+        });
+    }
+
+    /// Entries that match none of the detected GPUs
+    pub fn missing_gpus(&self, gpus: &[Gpu]) -> Vec<&GpuConfig> {
+        self.gpus.iter()
+            .filter(|entry| !gpus.iter().any(|gpu| entry.matches(gpu)))
+            .collect()
+    }
+}
+
+/// Speed values of any curve, for GUI sliders
+pub fn curve_speeds_f64(curve: &[(u32, u32)]) -> Vec<f64> {
+    curve.iter().map(|&(_, speed)| speed as f64).collect()
+}
+
+/// Put GUI speed values back on a curve. The points keep their x values,
+/// which are in whatever unit the curve's input uses.
+pub fn curve_with_speeds(curve: &[(u32, u32)], speeds: &[f64]) -> Vec<(u32, u32)> {
+    curve.iter()
+        .zip(speeds)
+        .map(|(&(x, _), &speed)| (x, speed as u32))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gpu(id: u32, uuid: &str, bus_id: &str) -> Gpu {
+        Gpu {
+            id,
+            name: format!("GPU {}", id),
+            uuid: Some(uuid.to_string()),
+            pci_bus_id: Some(bus_id.to_string()),
+            fans: vec![id],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_pci_bus_id_forms() {
+        assert_eq!(parse_pci_bus_id("00000000:0A:00.0"), Some((0, 10, 0, 0)));
+        assert_eq!(parse_pci_bus_id("0000:0a:00.0"), Some((0, 10, 0, 0)));
+        assert_eq!(parse_pci_bus_id("0a:00.0"), Some((0, 10, 0, 0)));
+        assert_eq!(parse_pci_bus_id("garbage"), None);
+    }
+
+    #[test]
+    fn test_gpu_configs_follow_the_card() {
+        let config: Config = serde_json::from_str(r#"{
+            "curve": [[30, 0], [80, 100]],
+            "interval_ms": 2000,
+            "gpus": [
+                { "uuid": "GPU-bbbb", "curve": [[30, 50], [80, 100]] },
+                { "pci_bus_id": "0a:00.0", "curve": [[30, 20], [80, 100]] },
+                { "uuid": "GPU-gone", "curve": [[30, 100]] }
+            ]
+        }"#).unwrap();
+
+        // Card B moved from slot 0 to slot 1; its curve moves with it
+        let gpus = vec![
+            gpu(0, "GPU-aaaa", "00000000:0A:00.0"),
+            gpu(1, "GPU-BBBB", "00000000:01:00.0"),
+            gpu(2, "GPU-cccc", "00000000:02:00.0"),
+        ];
+        assert_eq!(config.curve_for(&gpus[0]), &[(30, 20), (80, 100)]);
+        assert_eq!(config.curve_for(&gpus[1]), &[(30, 50), (80, 100)]);
+        assert_eq!(config.curve_for(&gpus[2]), &[(30, 0), (80, 100)]);
+
+        let missing = config.missing_gpus(&gpus);
+        assert_eq!(missing.len(), 1);
+        assert_eq!(missing[0].key(), "GPU-gone");
+
+        // Edits land on the curve the card actually follows
+        let mut config = config;
+        config.set_curve_for(&gpus[1], vec![(30, 60), (80, 100)]);
+        config.set_interpolation_for(&gpus[1], Interpolation::Step);
+        assert_eq!(config.curve_for(&gpus[1]), &[(30, 60), (80, 100)]);
+        assert_eq!(config.interpolation_for(&gpus[1]), Interpolation::Step);
+        assert_eq!(config.curve, vec![(30, 0), (80, 100)]);
+        assert_eq!(config.interpolation_for(&gpus[2]), config.interpolation);
+
+        config.set_curve_for(&gpus[2], vec![(30, 10), (80, 100)]);
+        assert_eq!(config.curve, vec![(30, 10), (80, 100)]);
+    }
+
+    #[test]
+    fn test_fan_overrides() {
+        let mut config = Config::default();
+        let card = gpu(0, "GPU-aaaa", "00000000:01:00.0");
+
+        // Nothing configured: every fan follows the shared curve
+        assert_eq!(config.fan_curve_for(&card, 1), config.curve.as_slice());
+        assert!(config.fan_config(&card, 1).is_none());
+
+        let fan = config.fan_config_mut(&card, 1).unwrap();
+        fan.curve = Some(vec![(30, 40), (80, 100)]);
+        fan.offset = -5;
+        config.fan_config_mut(&card, 0).unwrap().offset = 10;
+
+        assert_eq!(config.gpus.len(), 1);
+        assert_eq!(config.gpus[0].uuid.as_deref(), Some("GPU-aaaa"));
+        assert_eq!(config.fan_curve_for(&card, 1), &[(30, 40), (80, 100)]);
+        assert_eq!(config.fan_curve_for(&card, 0), config.curve.as_slice());
+        assert_eq!(config.fan_config(&card, 0).unwrap().offset, 10);
+
+        // Clearing the overrides removes the entries again
+        config.fan_config_mut(&card, 0).unwrap().offset = 0;
+        let fan = config.fan_config_mut(&card, 1).unwrap();
+        fan.curve = None;
+        fan.offset = 0;
+        config.prune();
+        assert!(config.gpus.is_empty());
+
+        // No key to store the override under
+        let anonymous = Gpu { id: 0, fans: vec![0], ..Default::default() };
+        assert!(config.fan_config_mut(&anonymous, 0).is_none());
+    }
+
+    #[test]
+    fn test_curve_inputs() {
+        let config: Config = serde_json::from_str(r#"{
+            "curve": [[30, 0], [80, 100]],
+            "interval_ms": 2000,
+            "extra_curves": [{ "input": "memory_temp", "curve": [[80, 40], [100, 100]] }],
+            "gpus": [{
+                "uuid": "GPU-aaaa",
+                "input": "power_draw",
+                "extra_curves": [],
+                "fans": [{ "index": 1, "input": "usage" }]
+            }]
+        }"#).unwrap();
+
+        let card = gpu(0, "GPU-aaaa", "00000000:01:00.0");
+        let other = gpu(1, "GPU-bbbb", "00000000:02:00.0");
+        assert_eq!(config.input_for(&other), CurveInput::CoreTemp);
+        assert_eq!(config.input_for(&card), CurveInput::PowerDraw);
+        assert_eq!(config.fan_input_for(&card, 0), CurveInput::PowerDraw);
+        assert_eq!(config.fan_input_for(&card, 1), CurveInput::Usage);
+
+        // An empty list on the GPU switches the shared extra curves off for it
+        assert_eq!(config.extra_curves_for(&other).len(), 1);
+        assert_eq!(config.extra_curves_for(&other)[0].input, CurveInput::MemoryTemp);
+        assert!(config.extra_curves_for(&card).is_empty());
     }
 }
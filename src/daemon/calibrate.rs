@@ -0,0 +1,329 @@
+//! Fan calibration
+//! Sweeps a GPU's fans through their duty range while the card is idle, measuring the
+//! steady-state RPM at each step and the lowest duty that gets a stopped fan going.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use anyhow::{bail, Context, Result};
+use log::{info, warn};
+
+use crate::config::{Config, FanCalibration, Interpolation};
+use crate::hardware::backend::GpuBackend;
+use crate::hardware::probe::Gpu;
+use super::math;
+
+/// Busier than this and the card is not idle enough to calibrate
+const MAX_IDLE_USAGE: u32 = 15;
+/// Stop and hand the fans back if the card gets this hot with its fans held low
+const MAX_TEMP: u32 = 80;
+/// Duty steps of the RPM sweep and of the start search
+const SWEEP_STEP: u32 = 10;
+const START_STEP: u32 = 5;
+/// RPM is read this often until two readings in a row agree
+const SETTLE_INTERVAL: Duration = Duration::from_secs(1);
+const MAX_SETTLE_READS: u32 = 15;
+/// Readings in a row a fan must turn in before a duty counts as its start,
+/// so a stray tachometer pulse from a stopped fan isn't taken for one
+const START_READS: u32 = 3;
+
+/// Measure every fan of one GPU; the fans are handed back to the driver afterwards,
+/// also when it fails or `cancel` is set. `wait` sleeps between readings, simulated time in tests.
+pub fn calibrate(backend: &dyn GpuBackend, gpu: &Gpu, cancel: &AtomicBool, wait: &mut dyn FnMut(Duration)) -> Result<Vec<FanCalibration>> {
+    let usage = backend.get_gpu_usage(gpu).context("reading GPU usage")?;
+    if usage > MAX_IDLE_USAGE {
+        bail!("GPU {} is {}% busy, calibrate while it is idle", gpu.id, usage);
+    }
+    let result = sweep(backend, gpu, cancel, wait);
+    if let Err(e) = backend.restore_auto(gpu) {
+        warn!("Failed to hand GPU {} fans back to the driver: {:#}", gpu.id, e);
+    }
+    result
+}
+
+/// Calibrate every GPU the backend finds
+pub fn calibrate_all(backend: &dyn GpuBackend, cancel: &AtomicBool, wait: &mut dyn FnMut(Duration)) -> Result<Vec<(Gpu, Vec<FanCalibration>)>> {
+    let mut results = Vec::new();
+    for gpu in backend.detect_gpus()? {
+        if gpu.fans.is_empty() {
+            continue;
+        }
+        info!("Calibrating {} fan(s) of GPU {} ({})", gpu.fans.len(), gpu.id, gpu.name);
+        let fans = calibrate(backend, &gpu, cancel, wait).with_context(|| format!("calibrating GPU {}", gpu.id))?;
+        for (fan_id, fan) in gpu.fans.iter().zip(&fans) {
+            info!("GPU {} fan {}: starts at {}%, {:?}", gpu.id, fan_id, fan.start_duty, fan.rpm);
+        }
+        results.push((gpu, fans));
+    }
+    Ok(results)
+}
+
+/// Store calibration results in the config, next to each fan's other settings
+pub fn store(config: &mut Config, results: Vec<(Gpu, Vec<FanCalibration>)>) -> Result<()> {
+    for (gpu, fans) in results {
+        for (index, fan) in fans.into_iter().enumerate() {
+            let Some(entry) = config.fan_config_mut(&gpu, index) else {
+                bail!("GPU {} has no UUID or PCI bus ID to store its calibration under", gpu.id);
+            };
+            entry.calibration = Some(fan);
+        }
+    }
+    Ok(())
+}
+
+/// RPM a calibrated fan should reach at a duty
+pub fn expected_rpm(calibration: &FanCalibration, duty: u32) -> u32 {
+    math::interpolate(duty as f64, &calibration.rpm, Interpolation::Linear).round().max(0.0) as u32
+}
+
+/// `--calibrate`: measure every fan and save the results; returns the exit code
+pub fn run(simulate: bool) -> i32 {
+    // A running daemon or app would keep rewriting the fans under the sweep
+    let others = super::supervisor::other_instances();
+    if !others.is_empty() {
+        log::error!("nvidia-wormhole is already running (pid {:?}), stop the daemon and close the app before calibrating", others);
+        return 1;
+    }
+    let mut config = super::supervisor::load_config();
+    let backend = crate::hardware::select_backend(&config, simulate);
+    info!("Calibrating fans, this takes a few minutes. Keep the GPU idle.");
+
+    let results = match calibrate_all(&*backend, &AtomicBool::new(false), &mut std::thread::sleep) {
+        Ok(results) => results,
+        Err(e) => {
+            log::error!("Calibration failed: {:#}", e);
+            return 1;
+        }
+    };
+    if let Err(e) = store(&mut config, results).and_then(|_| config.save()) {
+        log::error!("Failed to save calibration: {:#}", e);
+        return 1;
+    }
+    info!("Calibration saved");
+    0
+}
+
+fn sweep(backend: &dyn GpuBackend, gpu: &Gpu, cancel: &AtomicBool, wait: &mut dyn FnMut(Duration)) -> Result<Vec<FanCalibration>> {
+    let (min, max) = match backend.fan_speed_range(gpu.id) {
+        Ok((min, max)) if min <= max && max <= 100 => (min, max),
+        _ => (0, 100),
+    };
+
+    // From the top down, so the fans are already turning when they reach the low duties
+    let mut maps = vec![Vec::new(); gpu.fans.len()];
+    let mut duty = max;
+    loop {
+        check_cancel(cancel)?;
+        set_all(backend, gpu, duty)?;
+        let rpms = settle(backend, gpu, cancel, wait)?;
+        for (map, rpm) in maps.iter_mut().zip(rpms) {
+            map.insert(0, (duty, rpm));
+        }
+        if duty <= min {
+            break;
+        }
+        duty = duty.saturating_sub(SWEEP_STEP).max(min);
+    }
+
+    let start_duties = if min == 0 {
+        find_start_duties(backend, gpu, max, cancel, wait)?
+    } else {
+        // The card never stops its fans, so its minimum is where they start
+        vec![min; gpu.fans.len()]
+    };
+
+    Ok(start_duties.into_iter().zip(maps).map(|(start_duty, rpm)| FanCalibration { start_duty, rpm }).collect())
+}
+
+/// Lowest duty at which each fan gets going from standstill and stays running
+fn find_start_duties(backend: &dyn GpuBackend, gpu: &Gpu, max: u32, cancel: &AtomicBool, wait: &mut dyn FnMut(Duration)) -> Result<Vec<u32>> {
+    let mut starts: Vec<Option<u32>> = vec![None; gpu.fans.len()];
+    set_all(backend, gpu, 0)?;
+    settle(backend, gpu, cancel, wait)?;
+
+    let mut duty = START_STEP;
+    while duty <= max && starts.iter().any(Option::is_none) {
+        set_all(backend, gpu, duty)?;
+        let mut spinning: Vec<bool> = settle(backend, gpu, cancel, wait)?.iter().map(|&rpm| rpm > 0).collect();
+        for _ in 1..START_READS {
+            if !spinning.iter().zip(&starts).any(|(&spinning, start)| spinning && start.is_none()) {
+                break;
+            }
+            for (spinning, rpm) in spinning.iter_mut().zip(read_checked(backend, gpu, cancel, wait)?) {
+                *spinning &= rpm > 0;
+            }
+        }
+        for (start, spinning) in starts.iter_mut().zip(spinning) {
+            if start.is_none() && spinning {
+                *start = Some(duty);
+            }
+        }
+        duty += START_STEP;
+    }
+
+    starts.into_iter().zip(&gpu.fans)
+        .map(|(start, fan_id)| start.with_context(|| format!("fan {} did not start even at {}%", fan_id, max)))
+        .collect()
+}
+
+fn set_all(backend: &dyn GpuBackend, gpu: &Gpu, duty: u32) -> Result<()> {
+    for &fan_id in &gpu.fans {
+        backend.set_fan_speed(gpu.id, fan_id, duty)
+            .with_context(|| format!("setting fan {} to {}%", fan_id, duty))?;
+    }
+    Ok(())
+}
+
+/// Wait for every fan's RPM to stop changing, keeping an eye on the temperature
+/// and on `cancel` meanwhile
+fn settle(backend: &dyn GpuBackend, gpu: &Gpu, cancel: &AtomicBool, wait: &mut dyn FnMut(Duration)) -> Result<Vec<u32>> {
+    let mut last = read_rpms(backend, gpu)?;
+    for _ in 0..MAX_SETTLE_READS {
+        let rpms = read_checked(backend, gpu, cancel, wait)?;
+        // Within 3% and a little noise of the previous reading
+        let steady = rpms.iter().zip(&last).all(|(&rpm, &prev)| rpm.abs_diff(prev) <= prev * 3 / 100 + 30);
+        last = rpms;
+        if steady {
+            return Ok(last);
+        }
+    }
+    warn!("GPU {} fans did not settle, using the last reading", gpu.id);
+    Ok(last)
+}
+
+/// Read the RPMs after one interval, unless cancelled or the card got too hot
+fn read_checked(backend: &dyn GpuBackend, gpu: &Gpu, cancel: &AtomicBool, wait: &mut dyn FnMut(Duration)) -> Result<Vec<u32>> {
+    wait(SETTLE_INTERVAL);
+    check_cancel(cancel)?;
+    let temp = backend.get_temp(gpu.id).context("reading temperature")?;
+    if temp >= MAX_TEMP {
+        bail!("GPU {} reached {}°C, stopping", gpu.id, temp);
+    }
+    read_rpms(backend, gpu)
+}
+
+fn check_cancel(cancel: &AtomicBool) -> Result<()> {
+    if cancel.load(Ordering::Relaxed) {
+        bail!("calibration cancelled");
+    }
+    Ok(())
+}
+
+fn read_rpms(backend: &dyn GpuBackend, gpu: &Gpu) -> Result<Vec<u32>> {
+    gpu.fans.iter()
+        .map(|&fan_id| backend.get_fan_rpm(gpu.id, fan_id).with_context(|| format!("reading fan {} RPM", fan_id)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicU32;
+    use crate::config::SimulationConfig;
+    use crate::hardware::simulated::Simulated;
+
+    fn idle_card(min_fan_speed: u32) -> Simulated {
+        Simulated::manual(SimulationConfig { load_profile: vec![(60, 0)], min_fan_speed, ..SimulationConfig::default() })
+    }
+
+    #[test]
+    fn test_calibrates_simulated_fans() {
+        let sim = idle_card(0);
+        let gpu = sim.detect_gpus().unwrap().remove(0);
+        let fans = calibrate(&sim, &gpu, &AtomicBool::new(false), &mut |d| sim.advance(d.as_secs_f64())).unwrap();
+        assert_eq!(fans.len(), gpu.fans.len());
+
+        let fan = &fans[0];
+        let near = |duty: u32, rpm: u32| fan.rpm.iter().any(|&(d, r)| d == duty && r.abs_diff(rpm) <= rpm * 3 / 100 + 30);
+        // The virtual fans start at 25% but keep turning down to 15%
+        assert_eq!(fan.start_duty, 25);
+        assert_eq!(fan.rpm.first(), Some(&(0, 0)));
+        assert!(near(100, 3000) && near(50, 1500) && near(20, 600) && near(10, 0));
+        assert!(fan.rpm.windows(2).all(|w| w[0].0 < w[1].0 && w[0].1 <= w[1].1));
+        assert_eq!(expected_rpm(&FanCalibration { start_duty: 25, rpm: vec![(50, 1500), (60, 1800)] }, 55), 1650);
+        assert!(!sim.manual_control(0));
+
+        // A card that never stops its fans starts them at its minimum
+        let sim = idle_card(30);
+        let fans = calibrate(&sim, &gpu, &AtomicBool::new(false), &mut |d| sim.advance(d.as_secs_f64())).unwrap();
+        assert_eq!(fans[0].start_duty, 30);
+        assert_eq!(fans[0].rpm.first().map(|&(duty, _)| duty), Some(30));
+        assert!(fans[0].rpm[0].1.abs_diff(900) <= 60);
+    }
+
+    #[test]
+    fn test_calibration_needs_an_idle_card() {
+        let sim = Simulated::manual(SimulationConfig { load_profile: vec![(60, 90)], ..SimulationConfig::default() });
+        let gpu = sim.detect_gpus().unwrap().remove(0);
+        assert!(calibrate(&sim, &gpu, &AtomicBool::new(false), &mut |d| sim.advance(d.as_secs_f64())).is_err());
+
+        let mut config = Config::default();
+        let sim = idle_card(0);
+        let results = calibrate_all(&sim, &AtomicBool::new(false), &mut |d| sim.advance(d.as_secs_f64())).unwrap();
+        store(&mut config, results).unwrap();
+        assert_eq!(config.fan_calibration(&gpu, 1).map(|fan| fan.start_duty), Some(25));
+    }
+
+    #[test]
+    fn test_cancelled_calibration_hands_fans_back() {
+        let sim = idle_card(0);
+        let gpu = sim.detect_gpus().unwrap().remove(0);
+        let cancel = AtomicBool::new(false);
+        let mut waits = 0;
+        let result = calibrate(&sim, &gpu, &cancel, &mut |d| {
+            sim.advance(d.as_secs_f64());
+            waits += 1;
+            // Closed mid-sweep
+            if waits == 5 {
+                cancel.store(true, Ordering::Relaxed);
+            }
+        });
+        assert!(result.unwrap_err().to_string().contains("cancelled"));
+        assert_eq!(waits, 5);
+        assert!(!sim.manual_control(0));
+    }
+
+    /// Fan 0 reports one stray 20 RPM reading while the start search holds it at 10%
+    struct StrayPulse {
+        sim: Simulated,
+        /// Times fan 0 was set to 10%; the sweep is the first, the start search the second
+        at_ten: AtomicU32,
+        reads: AtomicU32,
+    }
+
+    impl GpuBackend for StrayPulse {
+        fn name(&self) -> &'static str { "stray pulse" }
+        fn detect_gpus(&self) -> Result<Vec<Gpu>> { self.sim.detect_gpus() }
+        fn get_temp(&self, gpu_id: u32) -> Result<u32> { self.sim.get_temp(gpu_id) }
+        fn get_gpu_usage(&self, gpu: &Gpu) -> Result<u32> { self.sim.get_gpu_usage(gpu) }
+        fn get_power_draw(&self, gpu: &Gpu) -> Result<u32> { self.sim.get_power_draw(gpu) }
+        fn get_memory_temp(&self, gpu: &Gpu) -> Result<u32> { self.sim.get_memory_temp(gpu) }
+        fn fan_speed_range(&self, gpu_id: u32) -> Result<(u32, u32)> { self.sim.fan_speed_range(gpu_id) }
+        fn get_fan_speed(&self, gpu_id: u32, fan_id: u32) -> Result<u32> { self.sim.get_fan_speed(gpu_id, fan_id) }
+        fn restore_auto(&self, gpu: &Gpu) -> Result<()> { self.sim.restore_auto(gpu) }
+        fn set_power_limit(&self, gpu: &Gpu, watts: Option<u32>) -> Result<()> { self.sim.set_power_limit(gpu, watts) }
+
+        fn get_fan_rpm(&self, gpu_id: u32, fan_id: u32) -> Result<u32> {
+            if fan_id == 0 && self.at_ten.load(Ordering::Relaxed) == 2 && self.reads.fetch_add(1, Ordering::Relaxed) == 1 {
+                return Ok(20);
+            }
+            self.sim.get_fan_rpm(gpu_id, fan_id)
+        }
+
+        fn set_fan_speed(&self, gpu_id: u32, fan_id: u32, speed: u32) -> Result<()> {
+            if fan_id == 0 && (speed == 10 || self.at_ten.load(Ordering::Relaxed) == 2) {
+                self.at_ten.fetch_add(1, Ordering::Relaxed);
+            }
+            self.sim.set_fan_speed(gpu_id, fan_id, speed)
+        }
+    }
+
+    #[test]
+    fn test_stray_pulse_is_not_a_start() {
+        let backend = StrayPulse { sim: idle_card(0), at_ten: AtomicU32::new(0), reads: AtomicU32::new(0) };
+        let gpu = backend.detect_gpus().unwrap().remove(0);
+        let fans = calibrate(&backend, &gpu, &AtomicBool::new(false), &mut |d| backend.sim.advance(d.as_secs_f64())).unwrap();
+        assert!(backend.reads.load(Ordering::Relaxed) >= 2);
+        assert_eq!(fans[0].start_duty, 25);
+        assert_eq!(fans[1].start_duty, 25);
+    }
+}
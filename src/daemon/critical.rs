@@ -0,0 +1,128 @@
+//! Critical temperature escalation
+//! A ladder of steps that kick in past fixed temperatures, whatever the curve says
+
+use std::thread;
+use log::{error, info};
+
+use crate::config::CriticalStep;
+use crate::hardware::probe::Gpu;
+use crate::util::run_host_command;
+
+/// A step stays active until the temperature drops this far below it
+const CLEAR_HYSTERESIS: u32 = 5;
+
+/// Which steps of the ladder are active for one GPU
+#[derive(Default)]
+pub struct Escalation {
+    active: Vec<bool>,
+}
+
+impl Escalation {
+    /// Feed a new reading and return the steps that just became active
+    pub fn update(&mut self, temp: u32, steps: &[CriticalStep]) -> Vec<usize> {
+        // The ladder may have been edited since the last tick
+        self.active.resize(steps.len(), false);
+
+        let mut raised = Vec::new();
+        for (index, step) in steps.iter().enumerate() {
+            if !self.active[index] && temp >= step.temp {
+                self.active[index] = true;
+                raised.push(index);
+            } else if self.active[index] && temp + CLEAR_HYSTERESIS <= step.temp {
+                self.active[index] = false;
+            }
+        }
+        raised
+    }
+
+    fn active_steps<'a>(&'a self, steps: &'a [CriticalStep]) -> impl Iterator<Item = &'a CriticalStep> {
+        steps.iter().zip(&self.active).filter(|(_, &active)| active).map(|(step, _)| step)
+    }
+
+    /// Highest fan speed demanded by an active step
+    pub fn fan_floor(&self, steps: &[CriticalStep]) -> Option<u32> {
+        self.active_steps(steps).filter_map(|step| step.fan_speed).max()
+    }
+
+    /// Lowest power limit demanded by an active step
+    pub fn power_limit(&self, steps: &[CriticalStep]) -> Option<u32> {
+        self.active_steps(steps).filter_map(|step| step.power_limit).min()
+    }
+
+    /// Threshold of the highest active step, for the status display
+    pub fn level(&self, steps: &[CriticalStep]) -> Option<u32> {
+        self.active_steps(steps).map(|step| step.temp).max()
+    }
+}
+
+/// Send the step's notification and run its command, without holding up the loop
+pub fn alert(gpu: &Gpu, temp: u32, step: &CriticalStep) {
+    if !step.notify && step.command.is_none() {
+        return;
+    }
+
+    let (gpu, step) = (gpu.clone(), step.clone());
+    thread::spawn(move || {
+        if step.notify {
+            let summary = format!("GPU {} at {}°C", gpu.id, temp);
+            notify(&summary, &format!("{} passed the critical threshold of {}°C", gpu.name, step.temp));
+        }
+
+        if let Some(command) = &step.command {
+            let gpu_var = format!("WORMHOLE_GPU={}", gpu.id);
+            let temp_var = format!("WORMHOLE_TEMP={}", temp);
+            match run_host_command("env", &[&gpu_var, &temp_var, "sh", "-c", command]) {
+                Ok(_) => info!("Critical command for GPU {} finished", gpu.id),
+                Err(e) => error!("Critical command for GPU {} failed: {}", gpu.id, e),
+            }
+        }
+    });
+}
+
+/// Critical desktop notification; blocks until notify-send returns
+pub fn notify(summary: &str, body: &str) {
+    if let Err(e) = run_host_command("notify-send", &["-u", "critical", summary, body]) {
+        error!("Failed to send notification: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ladder() -> Vec<CriticalStep> {
+        vec![
+            CriticalStep { temp: 88, fan_speed: Some(100), ..Default::default() },
+            CriticalStep { temp: 92, power_limit: Some(200), notify: true, ..Default::default() },
+        ]
+    }
+
+    #[test]
+    fn test_ladder_escalates_and_clears() {
+        let steps = ladder();
+        let mut escalation = Escalation::default();
+
+        assert!(escalation.update(80, &steps).is_empty());
+        assert_eq!(escalation.fan_floor(&steps), None);
+
+        assert_eq!(escalation.update(89, &steps), vec![0]);
+        assert_eq!(escalation.fan_floor(&steps), Some(100));
+        assert_eq!(escalation.power_limit(&steps), None);
+
+        // Jumping straight past both only raises the one not yet active
+        assert_eq!(escalation.update(95, &steps), vec![1]);
+        assert_eq!(escalation.power_limit(&steps), Some(200));
+        assert_eq!(escalation.level(&steps), Some(92));
+
+        // Hovering just under a threshold doesn't clear it or re-alert
+        assert!(escalation.update(90, &steps).is_empty());
+        assert_eq!(escalation.level(&steps), Some(92));
+
+        assert!(escalation.update(87, &steps).is_empty());
+        assert_eq!(escalation.power_limit(&steps), None);
+        assert_eq!(escalation.fan_floor(&steps), Some(100));
+
+        escalation.update(83, &steps);
+        assert_eq!(escalation.level(&steps), None);
+    }
+}
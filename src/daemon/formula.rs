@@ -0,0 +1,541 @@
+//! User-defined fan formula, for control a curve can't express.
+//! A tiny expression language with `let` bindings, evaluated without any access to the
+//! system, under limits on its size and on how long it may run.
+//!
+//! ```text
+//! let hot = max(temp - 60, 0);
+//! usage > 80 ? min(40 + hot * 3, 100) : prev * 0.9 + hot
+//! ```
+
+use std::time::{Duration, Instant};
+use anyhow::{anyhow, bail, Result};
+
+use crate::config::FormulaConfig;
+
+/// Values a formula can read, in slot order
+const VARIABLES: &[&str] = &["temp", "usage", "power", "prev", "elapsed", "dt"];
+const POWER_SLOT: usize = 2;
+
+/// Parser nesting limit, so a deeply nested formula can't exhaust the stack
+const MAX_DEPTH: usize = 64;
+const MAX_SOURCE_LEN: usize = 4096;
+
+/// What the formula sees on each tick
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FormulaInputs {
+    /// Core temperature in °C, after smoothing
+    pub temp: f64,
+    /// GPU utilization in percent
+    pub usage: f64,
+    /// Board power draw in watts, if the card reports it
+    pub power: Option<f64>,
+    /// The GPU's target speed on the previous tick, in percent
+    pub prev: f64,
+    /// Seconds since the daemon started controlling the GPU
+    pub elapsed: f64,
+    /// Seconds since the previous tick
+    pub dt: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Func {
+    Min,
+    Max,
+    Clamp,
+    Abs,
+    Sqrt,
+    Exp,
+    Ln,
+    Floor,
+    Ceil,
+    Round,
+}
+
+impl Func {
+    fn parse(name: &str) -> Option<(Func, usize)> {
+        Some(match name {
+            "min" => (Func::Min, 2),
+            "max" => (Func::Max, 2),
+            "clamp" => (Func::Clamp, 3),
+            "abs" => (Func::Abs, 1),
+            "sqrt" => (Func::Sqrt, 1),
+            "exp" => (Func::Exp, 1),
+            "ln" => (Func::Ln, 1),
+            "floor" => (Func::Floor, 1),
+            "ceil" => (Func::Ceil, 1),
+            "round" => (Func::Round, 1),
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Pow,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+    And,
+    Or,
+}
+
+#[derive(Debug)]
+enum Node {
+    Num(f64),
+    /// Index into the input variables followed by the `let` bindings
+    Slot(usize),
+    Neg(Box<Node>),
+    Not(Box<Node>),
+    Binary(BinOp, Box<Node>, Box<Node>),
+    Cond(Box<Node>, Box<Node>, Box<Node>),
+    Call(Func, Vec<Node>),
+}
+
+/// A parsed formula, ready to evaluate every tick
+#[derive(Debug)]
+pub struct Formula {
+    lets: Vec<Node>,
+    body: Node,
+    uses_power: bool,
+}
+
+impl Formula {
+    pub fn compile(source: &str, config: &FormulaConfig) -> Result<Self> {
+        if source.len() > MAX_SOURCE_LEN {
+            bail!("formula is longer than {} characters", MAX_SOURCE_LEN);
+        }
+        let mut parser = Parser {
+            tokens: tokenize(source)?,
+            pos: 0,
+            names: VARIABLES.iter().map(|name| name.to_string()).collect(),
+            nodes: 0,
+            max_nodes: config.max_nodes,
+            depth: 0,
+            uses_power: false,
+        };
+
+        let mut lets = Vec::new();
+        while parser.eat(&Token::Ident("let".into())) {
+            let name = match parser.next() {
+                Some(Token::Ident(name)) => name,
+                other => bail!("expected a name after `let`, found {}", describe(other.as_ref())),
+            };
+            parser.expect(Token::Assign)?;
+            lets.push(parser.expr()?);
+            parser.expect(Token::Semicolon)?;
+            // Bound only after its own expression, so it can't refer to itself
+            parser.names.push(name);
+        }
+        let body = parser.expr()?;
+        if let Some(token) = parser.next() {
+            bail!("unexpected {} after the end of the formula", describe(Some(&token)));
+        }
+        Ok(Self { lets, body, uses_power: parser.uses_power })
+    }
+
+    /// Whether the formula reads `power`, so it's only queried when needed
+    pub fn uses_power(&self) -> bool {
+        self.uses_power
+    }
+
+    pub fn eval(&self, inputs: &FormulaInputs, config: &FormulaConfig) -> Result<f64> {
+        let mut eval = Eval {
+            slots: vec![inputs.temp, inputs.usage, inputs.power.unwrap_or(f64::NAN), inputs.prev, inputs.elapsed, inputs.dt],
+            steps: 0,
+            max_steps: config.max_steps,
+            deadline: Instant::now() + Duration::from_millis(config.time_limit_ms),
+        };
+        if self.uses_power && inputs.power.is_none() {
+            bail!("power draw is not available");
+        }
+        for node in &self.lets {
+            let value = eval.eval(node)?;
+            eval.slots.push(value);
+        }
+        eval.eval(&self.body)
+    }
+}
+
+struct Eval {
+    slots: Vec<f64>,
+    steps: u64,
+    max_steps: u64,
+    deadline: Instant,
+}
+
+impl Eval {
+    fn eval(&mut self, node: &Node) -> Result<f64> {
+        self.steps += 1;
+        if self.steps > self.max_steps {
+            bail!("formula took more than {} steps", self.max_steps);
+        }
+        if self.steps.is_multiple_of(256) && Instant::now() > self.deadline {
+            bail!("formula ran out of time");
+        }
+
+        let truth = |value: f64| if value != 0.0 { 1.0 } else { 0.0 };
+        Ok(match node {
+            Node::Num(value) => *value,
+            Node::Slot(slot) => self.slots[*slot],
+            Node::Neg(inner) => -self.eval(inner)?,
+            Node::Not(inner) => 1.0 - truth(self.eval(inner)?),
+            Node::Cond(cond, then, otherwise) => {
+                if self.eval(cond)? != 0.0 {
+                    self.eval(then)?
+                } else {
+                    self.eval(otherwise)?
+                }
+            }
+            // Short-circuit, like the C-family operators they look like
+            Node::Binary(BinOp::And, a, b) => if self.eval(a)? != 0.0 { truth(self.eval(b)?) } else { 0.0 },
+            Node::Binary(BinOp::Or, a, b) => if self.eval(a)? != 0.0 { 1.0 } else { truth(self.eval(b)?) },
+            Node::Binary(op, a, b) => {
+                let (a, b) = (self.eval(a)?, self.eval(b)?);
+                let test = |cond: bool| if cond { 1.0 } else { 0.0 };
+                match op {
+                    BinOp::Add => a + b,
+                    BinOp::Sub => a - b,
+                    BinOp::Mul => a * b,
+                    BinOp::Div => a / b,
+                    BinOp::Rem => a % b,
+                    BinOp::Pow => a.powf(b),
+                    BinOp::Lt => test(a < b),
+                    BinOp::Le => test(a <= b),
+                    BinOp::Gt => test(a > b),
+                    BinOp::Ge => test(a >= b),
+                    BinOp::Eq => test(a == b),
+                    BinOp::Ne => test(a != b),
+                    BinOp::And | BinOp::Or => unreachable!(),
+                }
+            }
+            Node::Call(func, args) => {
+                let args = args.iter().map(|arg| self.eval(arg)).collect::<Result<Vec<_>>>()?;
+                match func {
+                    Func::Min => args[0].min(args[1]),
+                    Func::Max => args[0].max(args[1]),
+                    Func::Clamp => args[0].max(args[1]).min(args[2]),
+                    Func::Abs => args[0].abs(),
+                    Func::Sqrt => args[0].sqrt(),
+                    Func::Exp => args[0].exp(),
+                    Func::Ln => args[0].ln(),
+                    Func::Floor => args[0].floor(),
+                    Func::Ceil => args[0].ceil(),
+                    Func::Round => args[0].round(),
+                }
+            }
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Num(f64),
+    Ident(String),
+    Op(&'static str),
+    LParen,
+    RParen,
+    Comma,
+    Question,
+    Colon,
+    Assign,
+    Semicolon,
+}
+
+fn describe(token: Option<&Token>) -> String {
+    match token {
+        None => "end of formula".to_string(),
+        Some(Token::Num(value)) => format!("number {}", value),
+        Some(Token::Ident(name)) => format!("`{}`", name),
+        Some(Token::Op(op)) => format!("`{}`", op),
+        Some(token) => format!("`{}`", match token {
+            Token::LParen => "(",
+            Token::RParen => ")",
+            Token::Comma => ",",
+            Token::Question => "?",
+            Token::Colon => ":",
+            Token::Assign => "=",
+            _ => ";",
+        }),
+    }
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>> {
+    const OPERATORS: &[&str] = &["<=", ">=", "==", "!=", "&&", "||", "+", "-", "*", "/", "%", "^", "<", ">", "!"];
+
+    let mut tokens = Vec::new();
+    let mut rest = source;
+    while let Some(c) = rest.chars().next() {
+        if c.is_whitespace() {
+            rest = &rest[c.len_utf8()..];
+            continue;
+        }
+        if c == '#' {
+            // Comment to the end of the line
+            rest = rest.find('\n').map_or("", |end| &rest[end..]);
+            continue;
+        }
+        if c.is_ascii_digit() || c == '.' {
+            let end = rest.find(|c: char| !(c.is_ascii_digit() || c == '.')).unwrap_or(rest.len());
+            let value = rest[..end].parse().map_err(|_| anyhow!("bad number {:?}", &rest[..end]))?;
+            tokens.push(Token::Num(value));
+            rest = &rest[end..];
+            continue;
+        }
+        if c.is_ascii_alphabetic() || c == '_' {
+            let end = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(rest.len());
+            tokens.push(Token::Ident(rest[..end].to_string()));
+            rest = &rest[end..];
+            continue;
+        }
+        if let Some(op) = OPERATORS.iter().find(|op| rest.starts_with(**op)) {
+            tokens.push(Token::Op(op));
+            rest = &rest[op.len()..];
+            continue;
+        }
+        tokens.push(match c {
+            '(' => Token::LParen,
+            ')' => Token::RParen,
+            ',' => Token::Comma,
+            '?' => Token::Question,
+            ':' => Token::Colon,
+            '=' => Token::Assign,
+            ';' => Token::Semicolon,
+            _ => bail!("unexpected character {:?}", c),
+        });
+        rest = &rest[c.len_utf8()..];
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    /// Input variables, then `let` bindings in order
+    names: Vec<String>,
+    nodes: usize,
+    max_nodes: usize,
+    depth: usize,
+    uses_power: bool,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn eat(&mut self, token: &Token) -> bool {
+        if self.peek() == Some(token) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, token: Token) -> Result<()> {
+        if self.eat(&token) {
+            return Ok(());
+        }
+        bail!("expected {}, found {}", describe(Some(&token)), describe(self.peek()))
+    }
+
+    /// Count every node built, so a formula's memory use is bounded
+    fn node(&mut self, node: Node) -> Result<Node> {
+        self.nodes += 1;
+        if self.nodes > self.max_nodes {
+            bail!("formula is larger than {} nodes", self.max_nodes);
+        }
+        Ok(node)
+    }
+
+    fn eat_op(&mut self, ops: &[&'static str]) -> Option<&'static str> {
+        match self.peek() {
+            Some(Token::Op(op)) if ops.contains(op) => {
+                let op = *op;
+                self.pos += 1;
+                Some(op)
+            }
+            _ => None,
+        }
+    }
+
+    /// One level deeper into the recursion; the caller steps back out by decrementing `depth`
+    fn descend(&mut self) -> Result<()> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            bail!("formula nests deeper than {} levels", MAX_DEPTH);
+        }
+        Ok(())
+    }
+
+    fn expr(&mut self) -> Result<Node> {
+        self.descend()?;
+        let cond = self.binary(0)?;
+        let node = if self.eat(&Token::Question) {
+            let then = self.expr()?;
+            self.expect(Token::Colon)?;
+            let otherwise = self.expr()?;
+            self.node(Node::Cond(Box::new(cond), Box::new(then), Box::new(otherwise)))?
+        } else {
+            cond
+        };
+        self.depth -= 1;
+        Ok(node)
+    }
+
+    /// Binary operators by precedence level, loosest first
+    fn binary(&mut self, level: usize) -> Result<Node> {
+        const LEVELS: &[&[&str]] = &[&["||"], &["&&"], &["<", "<=", ">", ">=", "==", "!="], &["+", "-"], &["*", "/", "%"]];
+        if level == LEVELS.len() {
+            return self.unary();
+        }
+        let mut node = self.binary(level + 1)?;
+        while let Some(op) = self.eat_op(LEVELS[level]) {
+            let rhs = self.binary(level + 1)?;
+            let op = match op {
+                "||" => BinOp::Or,
+                "&&" => BinOp::And,
+                "<" => BinOp::Lt,
+                "<=" => BinOp::Le,
+                ">" => BinOp::Gt,
+                ">=" => BinOp::Ge,
+                "==" => BinOp::Eq,
+                "!=" => BinOp::Ne,
+                "+" => BinOp::Add,
+                "-" => BinOp::Sub,
+                "*" => BinOp::Mul,
+                "/" => BinOp::Div,
+                _ => BinOp::Rem,
+            };
+            node = self.node(Node::Binary(op, Box::new(node), Box::new(rhs)))?;
+        }
+        Ok(node)
+    }
+
+    fn unary(&mut self) -> Result<Node> {
+        match self.eat_op(&["-", "!"]) {
+            Some(op) => {
+                self.descend()?;
+                let inner = Box::new(self.unary()?);
+                self.depth -= 1;
+                self.node(if op == "-" { Node::Neg(inner) } else { Node::Not(inner) })
+            }
+            None => self.power(),
+        }
+    }
+
+    fn power(&mut self) -> Result<Node> {
+        let base = self.atom()?;
+        if self.eat_op(&["^"]).is_some() {
+            // Right-associative, and binds tighter than a unary minus on its left.
+            // Each `^` nests one level, like a parenthesis would.
+            self.descend()?;
+            let exponent = self.unary()?;
+            self.depth -= 1;
+            return self.node(Node::Binary(BinOp::Pow, Box::new(base), Box::new(exponent)));
+        }
+        Ok(base)
+    }
+
+    fn atom(&mut self) -> Result<Node> {
+        match self.next() {
+            Some(Token::Num(value)) => self.node(Node::Num(value)),
+            Some(Token::LParen) => {
+                let node = self.expr()?;
+                self.expect(Token::RParen)?;
+                Ok(node)
+            }
+            Some(Token::Ident(name)) if self.peek() == Some(&Token::LParen) => {
+                let (func, arity) = Func::parse(&name).ok_or_else(|| anyhow!("unknown function `{}`", name))?;
+                self.pos += 1;
+                let mut args = Vec::new();
+                if !self.eat(&Token::RParen) {
+                    loop {
+                        args.push(self.expr()?);
+                        if self.eat(&Token::RParen) {
+                            break;
+                        }
+                        self.expect(Token::Comma)?;
+                    }
+                }
+                if args.len() != arity {
+                    bail!("`{}` takes {} argument(s), got {}", name, arity, args.len());
+                }
+                self.node(Node::Call(func, args))
+            }
+            Some(Token::Ident(name)) => {
+                // The latest binding of a name wins
+                let slot = self.names.iter().rposition(|known| *known == name)
+                    .ok_or_else(|| anyhow!("unknown variable `{}`", name))?;
+                self.uses_power |= slot == POWER_SLOT;
+                self.node(Node::Slot(slot))
+            }
+            other => bail!("expected a value, found {}", describe(other.as_ref())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(source: &str, inputs: FormulaInputs) -> Result<f64> {
+        let config = FormulaConfig::default();
+        Formula::compile(source, &config)?.eval(&inputs, &config)
+    }
+
+    #[test]
+    fn test_formula_language() {
+        let inputs = FormulaInputs { temp: 70.0, usage: 90.0, power: Some(200.0), prev: 40.0, elapsed: 10.0, dt: 2.0 };
+        assert_eq!(run("temp", inputs).unwrap(), 70.0);
+        assert_eq!(run("1 + 2 * 3 - 4 / 2", inputs).unwrap(), 5.0);
+        assert_eq!(run("-2 ^ 2 + 2 ^ 3 ^ 2 / 64", inputs).unwrap(), 4.0);
+        assert_eq!(run("usage > 80 && temp >= 70 ? 100 : 20", inputs).unwrap(), 100.0);
+        assert_eq!(run("!(usage > 80) || power < 100 ? 100 : 20", inputs).unwrap(), 20.0);
+        assert_eq!(run("clamp(prev + (temp - 65) * dt, 30, 60)", inputs).unwrap(), 50.0);
+        assert_eq!(run("min(max(power / 4, 10), round(elapsed * 1.26))", inputs).unwrap(), 13.0);
+
+        let script = "
+            let hot = max(temp - 60, 0);   # degrees over 60
+            let hot = hot * 2;
+            hot + 30
+        ";
+        assert_eq!(run(script, inputs).unwrap(), 50.0);
+    }
+
+    #[test]
+    fn test_formula_errors_and_limits() {
+        let inputs = FormulaInputs { temp: 70.0, ..Default::default() };
+        for bad in ["temp +", "tmp", "sin(temp)", "min(temp)", "let x = x; x", "temp temp", "(temp", "temp $ 2"] {
+            assert!(run(bad, inputs).is_err(), "{:?} should not compile", bad);
+        }
+        // Power is asked for but the card doesn't report it
+        assert!(run("power / 3", inputs).is_err());
+        assert!(!Formula::compile("temp", &FormulaConfig::default()).unwrap().uses_power());
+
+        // Too big to build, too deep to parse, too long to run
+        let config = FormulaConfig { max_nodes: 50, ..FormulaConfig::default() };
+        assert!(Formula::compile(&vec!["temp"; 40].join(" + "), &config).is_err());
+        assert!(run(&format!("{}temp{}", "(".repeat(100), ")".repeat(100)), inputs).is_err());
+        let config = FormulaConfig { max_nodes: 100_000, ..FormulaConfig::default() };
+        let err = Formula::compile(&vec!["2"; 500].join("^"), &config).unwrap_err();
+        assert!(err.to_string().contains("deeper"), "{}", err);
+        let config = FormulaConfig { max_steps: 100, ..FormulaConfig::default() };
+        let formula = Formula::compile(&vec!["temp"; 60].join(" + "), &config).unwrap();
+        assert!(formula.eval(&inputs, &config).is_err());
+    }
+}
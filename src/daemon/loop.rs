@@ -1,31 +1,671 @@
+use std::cell::OnceCell;
+use std::panic::{self, AssertUnwindSafe};
 use std::sync::{atomic::{AtomicBool, Ordering}, Arc, Mutex};
-use std::thread;
-use std::time::Duration;
-use log::{info, error};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use log::{info, warn, error};
 
-use crate::hardware::nvidia;
-use super::math;
+use crate::config::{Config, ControlMode, CurveInput, FailSafeAction, Interpolation};
+use crate::hardware::backend::GpuBackend;
+use crate::hardware::probe::Gpu;
+use crate::hardware::sensors::Sensors;
+use super::calibrate;
+use super::critical::{self, Escalation};
+use super::formula::{Formula, FormulaInputs};
+use super::stall::{StallDetector, StallEvent};
+use super::math::{self, FeedForward, Pid, Ramp, Smoother, ZeroRpm, ZeroRpmAction};
 
 #[derive(Clone, Default)]
-pub struct SharedStatus {
+pub struct GpuStatus {
+    pub gpu: Gpu,
     pub current_temp: u32,
+    /// Output of the GPU's curve, before per-fan overrides
     pub current_speed: u32,
     pub gpu_usage: u32,
+    /// Target speed of each fan, in the order of `gpu.fans`
+    pub fan_speeds: Vec<u32>,
+    /// Speed each fan was read back at, in percent and RPM, where the card reports it
+    pub actual_speeds: Vec<Option<u32>>,
+    pub fan_rpms: Vec<Option<u32>>,
+    /// Fans that stopped or slowed down on their own, in the order of `gpu.fans`
+    pub failed_fans: Vec<bool>,
+    /// Set while the sensor fail-safe is engaged, with the last bad reading
+    pub fail_safe: Option<String>,
+    /// Threshold of the highest critical step in effect
+    pub critical: Option<u32>,
+    /// Temperature being held in PID mode
+    pub pid_target: Option<u32>,
+    /// Set while hybrid mode leaves the fans to the driver, with the takeover temperature
+    pub driver_until: Option<u32>,
+}
+
+#[derive(Clone, Default)]
+pub struct SharedStatus {
+    pub gpus: Vec<GpuStatus>,
 }
 
 #[derive(Clone)]
 pub struct DaemonState {
     pub running: Arc<AtomicBool>,
-    pub curve: Arc<Mutex<Vec<(u32, u32)>>>,
+    pub config: Arc<Mutex<Config>>,
     pub status: Arc<Mutex<SharedStatus>>,
+    pub backend: Arc<dyn GpuBackend>,
+    worker: Arc<Mutex<Option<JoinHandle<()>>>>,
+}
+
+/// Readings at or above this are sensor garbage (255 is a common "no data" value)
+const MAX_PLAUSIBLE_TEMP: u32 = 150;
+
+/// Control state for one GPU, kept across ticks
+struct GpuControl {
+    gpu: Gpu,
+    /// Last speed successfully written to each fan
+    last_speeds: Vec<Option<u32>>,
+    /// Since when each fan has been reading back off its target
+    drift_since: Vec<Option<Instant>>,
+    stalls: StallDetector,
+    /// Last accepted reading, to spot sudden jumps
+    last_temp: Option<u32>,
+    /// A reading rejected as a jump, believed if the next one agrees with it
+    jumped_temp: Option<u32>,
+    /// Temperature the curves were evaluated at last tick, lagging falls by the hysteresis
+    curve_temp: Option<u32>,
+    smoother: Smoother,
+    /// Rate limits for the GPU's curve output and for each fan
+    speed_ramp: Ramp,
+    fan_ramps: Vec<Ramp>,
+    /// When the last good reading was handled, for the ramp rates
+    last_tick: Option<Instant>,
+    zero_rpm: ZeroRpm,
+    pid: Pid,
+    feed_forward: FeedForward,
+    /// Bad readings in a row
+    failures: u32,
+    /// Why the fail-safe is engaged, if it is
+    fail_safe: Option<String>,
+    escalation: Escalation,
+    /// Power limit we last applied, None while the card runs at its default
+    power_limit: Option<u32>,
+    /// Inputs the card failed to report, so each is only warned about once
+    missing_inputs: Vec<CurveInput>,
+    /// The user's formula, compiled from the expression and node limit it was built with
+    formula: Option<(String, usize, Result<Formula, String>)>,
+    /// Set while the formula fails and the curves stand in, to log each streak once
+    formula_failing: bool,
+    /// Target speed of the previous tick
+    last_target: Option<u32>,
+    /// Speeds the card accepts, asked for on first use
+    fan_range: Option<(u32, u32)>,
+    /// The driver runs the fans: before the first tick, and in hybrid mode while the card is cool
+    driver_in_charge: bool,
+    started: Instant,
+}
+
+/// This tick's value of each curve input, read from the card only when a curve needs it
+struct Readings<'a> {
+    backend: &'a dyn GpuBackend,
+    sensors: &'a Sensors,
+    config: &'a Config,
+    gpu: &'a Gpu,
+    /// Core temperature after smoothing and hysteresis
+    core_temp: u32,
+    usage: Option<u32>,
+    power_draw: OnceCell<Option<u32>>,
+    memory_temp: OnceCell<Option<u32>>,
+}
+
+impl Readings<'_> {
+    fn get(&self, input: &CurveInput) -> Option<u32> {
+        match input {
+            CurveInput::CoreTemp => Some(self.core_temp),
+            CurveInput::Usage => self.usage,
+            CurveInput::PowerDraw => *self.power_draw.get_or_init(|| self.backend.get_power_draw(self.gpu).ok()),
+            CurveInput::MemoryTemp => *self.memory_temp.get_or_init(|| self.backend.get_memory_temp(self.gpu).ok()),
+            CurveInput::Sensor(name) => match self.sensors.read(name, self.config, self.core_temp) {
+                Ok(value) => Some(value.round().max(0.0) as u32),
+                Err(e) => {
+                    log::debug!("GPU {}: sensor {:?}: {:#}", self.gpu.id, name, e);
+                    None
+                }
+            },
+        }
+    }
+}
+
+impl GpuControl {
+    fn new(gpu: Gpu) -> Self {
+        let last_speeds = vec![None; gpu.fans.len()];
+        let drift_since = vec![None; gpu.fans.len()];
+        let fan_ramps = gpu.fans.iter().map(|_| Ramp::default()).collect();
+        Self {
+            gpu,
+            last_speeds,
+            drift_since,
+            stalls: StallDetector::default(),
+            last_temp: None,
+            jumped_temp: None,
+            curve_temp: None,
+            smoother: Smoother::default(),
+            speed_ramp: Ramp::default(),
+            fan_ramps,
+            last_tick: None,
+            zero_rpm: ZeroRpm::default(),
+            pid: Pid::default(),
+            feed_forward: FeedForward::default(),
+            failures: 0,
+            fail_safe: None,
+            escalation: Escalation::default(),
+            power_limit: None,
+            missing_inputs: Vec::new(),
+            formula: None,
+            formula_failing: false,
+            last_target: None,
+            fan_range: None,
+            driver_in_charge: true,
+            started: Instant::now(),
+        }
+    }
+
+    /// Speed from the user's formula, or None to fall back to the curves
+    fn formula_speed(&mut self, readings: &Readings, config: &Config, temp: f64, dt: f64) -> Option<u32> {
+        let settings = &config.formula;
+        let stale = !self.formula.as_ref()
+            .is_some_and(|(source, max_nodes, _)| *source == settings.expression && *max_nodes == settings.max_nodes);
+        if stale {
+            let compiled = Formula::compile(&settings.expression, settings).map_err(|e| format!("{:#}", e));
+            if let Err(e) = &compiled {
+                error!("GPU {}: formula does not compile, following the curves instead: {}", self.gpu.id, e);
+            }
+            self.formula = Some((settings.expression.clone(), settings.max_nodes, compiled));
+            self.formula_failing = false;
+        }
+        let formula = self.formula.as_ref()?.2.as_ref().ok()?;
+
+        let inputs = FormulaInputs {
+            temp,
+            usage: readings.usage.unwrap_or(0) as f64,
+            power: if formula.uses_power() { readings.get(&CurveInput::PowerDraw).map(f64::from) } else { None },
+            prev: self.last_target.unwrap_or(0) as f64,
+            elapsed: self.started.elapsed().as_secs_f64(),
+            dt,
+        };
+        let failure = match formula.eval(&inputs, settings) {
+            Ok(speed) if (0.0..=100.0).contains(&speed) => {
+                if std::mem::take(&mut self.formula_failing) {
+                    info!("GPU {}: formula is working again", self.gpu.id);
+                }
+                return Some(speed.round() as u32);
+            }
+            Ok(speed) => format!("returned {}, outside 0-100%", speed),
+            Err(e) => e.to_string(),
+        };
+        if !std::mem::replace(&mut self.formula_failing, true) {
+            warn!("GPU {}: formula {}, following the curves instead", self.gpu.id, failure);
+        }
+        None
+    }
+
+    /// Speed from a curve on the given input, raised by any higher extra curve.
+    /// If the input can't be read a core temperature curve stands in,
+    /// since the curve's own points are in another unit.
+    fn curve_speed(&mut self, readings: &Readings, config: &Config, input: &CurveInput, curve: &[(u32, u32)], interpolation: Interpolation) -> u32 {
+        let speed = match self.reading(readings, input) {
+            Some(value) => math::calculate_target_speed(value, curve, interpolation),
+            None => {
+                let (curve, interpolation) = config.core_temp_curve_for(&self.gpu);
+                math::calculate_target_speed(readings.core_temp, curve, interpolation)
+            }
+        };
+
+        config.extra_curves_for(&self.gpu)
+            .iter()
+            .filter_map(|extra| {
+                let value = self.reading(readings, &extra.input)?;
+                let interpolation = extra.interpolation.unwrap_or(interpolation);
+                Some(math::calculate_target_speed(value, &extra.curve, interpolation))
+            })
+            .fold(speed, u32::max)
+    }
+
+    fn reading(&mut self, readings: &Readings, input: &CurveInput) -> Option<u32> {
+        let value = readings.get(input);
+        if value.is_none() && !self.missing_inputs.contains(input) {
+            warn!("GPU {}: {} is not available, curves on it fall back to a core temperature curve or are skipped", self.gpu.id, input);
+            self.missing_inputs.push(input.clone());
+        }
+        value
+    }
+
+    fn tick(&mut self, backend: &dyn GpuBackend, config: &Config, sensors: &Sensors, status: &Mutex<SharedStatus>) {
+        let gpu_id = self.gpu.id;
+        let temp = match self.read_temp(backend, config) {
+            Ok(temp) => temp,
+            Err(reason) => {
+                error!("GPU {}: {}", gpu_id, reason);
+                self.failures += 1;
+                if self.failures >= config.fail_safe.max_failures.max(1) && self.fail_safe.is_none() {
+                    self.engage_fail_safe(backend, config, status, reason);
+                }
+                return;
+            }
+        };
+
+        self.failures = 0;
+        if self.fail_safe.take().is_some() {
+            info!("GPU {}: temperature readings are back, leaving fail-safe", gpu_id);
+        }
+
+        let now = Instant::now();
+        let dt = self.last_tick.replace(now).map_or(0.0, |last| now.duration_since(last).as_secs_f64());
+
+        let smoothed_temp = self.smoother.update(temp, &config.smoothing);
+        let smoothed = smoothed_temp.round() as u32;
+        let curve_temp = math::hysteresis_temp(smoothed, self.curve_temp, config.hysteresis);
+        self.curve_temp = Some(curve_temp);
+        let usage = backend.get_gpu_usage(&self.gpu).ok();
+
+        if self.hybrid_handover(backend, config, temp, smoothed) {
+            self.update_status(status, |gpu_status| {
+                gpu_status.current_temp = temp;
+                gpu_status.current_speed = 0;
+                gpu_status.gpu_usage = usage.unwrap_or(0);
+                gpu_status.fan_speeds.clear();
+                gpu_status.actual_speeds.clear();
+                gpu_status.fan_rpms.clear();
+                gpu_status.fail_safe = None;
+                gpu_status.critical = None;
+                gpu_status.pid_target = None;
+                gpu_status.driver_until = config.hybrid.as_ref().map(|hybrid| hybrid.takeover_temp);
+            });
+            return;
+        }
+
+        // In PID mode one controller output replaces every curve
+        let pid_speed = match config.mode {
+            ControlMode::Pid => Some(self.pid.update(smoothed_temp, &config.pid, dt).round() as u32),
+            ControlMode::Curve | ControlMode::Formula => {
+                self.pid.reset();
+                None
+            }
+        };
+
+        // Added on top of the curve so the fans get going before the temperature catches up
+        let boost = match &config.feed_forward {
+            Some(feed_forward) => self.feed_forward.update(smoothed_temp, usage, feed_forward, dt),
+            None => {
+                self.feed_forward.reset();
+                0.0
+            }
+        };
+        let boosted = |speed: u32| (speed as f64 + boost).round().min(100.0) as u32;
+
+        // The curves below need `self` mutably while the readings hold on to the card
+        let gpu = self.gpu.clone();
+        let readings = Readings {
+            backend,
+            sensors,
+            config,
+            gpu: &gpu,
+            core_temp: curve_temp,
+            usage,
+            power_draw: OnceCell::new(),
+            memory_temp: OnceCell::new(),
+        };
+        let formula_speed = match config.mode {
+            ControlMode::Formula => self.formula_speed(&readings, config, smoothed_temp, dt),
+            ControlMode::Curve | ControlMode::Pid => None,
+        };
+        // A controller's output replaces every curve, per-fan offsets still apply
+        let fixed_speed = pid_speed.or(formula_speed);
+        let speed = match fixed_speed {
+            Some(speed) => speed,
+            None => {
+                let (input, interpolation) = (config.input_for(&self.gpu), config.interpolation_for(&self.gpu));
+                self.curve_speed(&readings, config, &input, config.curve_for(&self.gpu), interpolation)
+            }
+        };
+        let mut target_speed = self.speed_ramp.step(boosted(speed) as f64, &config.ramp, dt).round() as u32;
+        
+        // Each fan may have its own curve, input and offset
+        let mut fan_speeds = Vec::with_capacity(self.gpu.fans.len());
+        for index in 0..self.gpu.fans.len() {
+            let speed = match fixed_speed {
+                Some(speed) => speed,
+                None => {
+                    let input = config.fan_input_for(&self.gpu, index);
+                    let interpolation = config.fan_interpolation_for(&self.gpu, index);
+                    self.curve_speed(&readings, config, &input, config.fan_curve_for(&self.gpu, index), interpolation)
+                }
+            };
+            let offset = config.fan_config(&self.gpu, index).map(|fan| fan.offset).unwrap_or(0);
+            let speed = math::apply_offset(boosted(speed), offset);
+            let speed = self.fan_ramps[index].step(speed as f64, &config.ramp, dt).round() as u32;
+            fan_speeds.push(math::apply_deadband(speed, self.last_speeds[index], config.deadband));
+        }
+        let usage = usage.unwrap_or(0);
+
+        if let Some(zero_rpm) = &config.zero_rpm {
+            match self.zero_rpm.update(smoothed, now, zero_rpm) {
+                ZeroRpmAction::Stop => self.override_speeds(&mut target_speed, &mut fan_speeds, 0, false),
+                ZeroRpmAction::Kick(kick) => self.override_speeds(&mut target_speed, &mut fan_speeds, kick, true),
+                ZeroRpmAction::Curve => {}
+            }
+        }
+
+        // The critical ladder overrides whatever the curves said
+        for index in self.escalation.update(temp, &config.critical) {
+            let step = &config.critical[index];
+            warn!("GPU {}: {}°C reached critical step at {}°C", gpu_id, temp, step.temp);
+            critical::alert(&self.gpu, temp, step);
+        }
+        if let Some(floor) = self.escalation.fan_floor(&config.critical) {
+            self.override_speeds(&mut target_speed, &mut fan_speeds, floor.min(100), true);
+        }
+        self.apply_power_limit(backend, self.escalation.power_limit(&config.critical));
+        let critical = self.escalation.level(&config.critical);
+
+        // With a fan down, the rest of the card's fans have to make up for it
+        if self.stalls.any_failed() {
+            self.override_speeds(&mut target_speed, &mut fan_speeds, 100, true);
+        }
+
+        // The driver would move out-of-range writes anyway; clamp so the status shows the real speed
+        let (min, max) = self.fan_range(backend);
+        target_speed = target_speed.clamp(min, max);
+        fan_speeds.iter_mut().for_each(|speed| *speed = (*speed).clamp(min, max));
+        // A calibrated fan would just sit still below its start duty
+        for (index, speed) in fan_speeds.iter_mut().enumerate() {
+            if let Some(fan) = config.fan_calibration(&self.gpu, index) {
+                if *speed > 0 && *speed < fan.start_duty {
+                    *speed = fan.start_duty.min(max);
+                }
+            }
+        }
+        
+        info!("GPU {}: Temp: {}°C, Usage: {}% -> Target Speed: {}% (fans: {:?})", gpu_id, temp, usage, target_speed, fan_speeds);
+        
+        // Update Shared Status
+        self.update_status(status, |gpu_status| {
+            gpu_status.current_temp = temp;
+            gpu_status.current_speed = target_speed;
+            gpu_status.gpu_usage = usage;
+            gpu_status.fan_speeds = fan_speeds.clone();
+            gpu_status.fail_safe = None;
+            gpu_status.critical = critical;
+            gpu_status.pid_target = pid_speed.map(|_| config.pid.target_temp);
+            gpu_status.driver_until = None;
+        });
+
+        self.last_target = Some(target_speed);
+        self.write_speeds(backend, &fan_speeds);
+        if config.verify.enabled || config.stall.enabled {
+            self.read_back(backend, config, &fan_speeds, status);
+        }
+    }
+
+    /// Read back what the fans are doing, to catch fans that don't follow their target
+    fn read_back(&mut self, backend: &dyn GpuBackend, config: &Config, fan_speeds: &[u32], status: &Mutex<SharedStatus>) {
+        let gpu_id = self.gpu.id;
+        // Only stall detection needs the RPM; on nvidia-settings every query is a process
+        let (actual, rpms): (Vec<Option<u32>>, Vec<Option<u32>>) = self.gpu.fans.iter()
+            .map(|&fan_id| backend.read_fan(gpu_id, fan_id, config.stall.enabled))
+            .unzip();
+
+        if config.verify.enabled {
+            self.verify_speeds(backend, config, fan_speeds, &actual);
+        }
+        if config.stall.enabled {
+            self.check_stalls(config, fan_speeds, &actual, &rpms);
+        }
+
+        let failed_fans = (0..self.gpu.fans.len()).map(|index| self.stalls.failed(index)).collect();
+        self.update_status(status, |gpu_status| {
+            gpu_status.actual_speeds = actual;
+            gpu_status.fan_rpms = rpms;
+            gpu_status.failed_fans = failed_fans;
+        });
+    }
+
+    /// A fan that stays off target past the grace period was most likely put back
+    /// in auto mode by a driver reset or another tool, so write it again.
+    fn verify_speeds(&mut self, backend: &dyn GpuBackend, config: &Config, fan_speeds: &[u32], actual: &[Option<u32>]) {
+        let gpu_id = self.gpu.id;
+        let now = Instant::now();
+        let grace = Duration::from_secs(config.verify.grace_secs);
+        let mut reassert = false;
+        for (index, &fan_id) in self.gpu.fans.iter().enumerate() {
+            let drifting = actual[index].is_some_and(|speed| speed.abs_diff(fan_speeds[index]) > config.verify.tolerance);
+            if !drifting {
+                self.drift_since[index] = None;
+                continue;
+            }
+            let since = *self.drift_since[index].get_or_insert(now);
+            if now.duration_since(since) >= grace {
+                warn!(
+                    "GPU {} fan {} runs at {}% instead of {}%, taking control again",
+                    gpu_id, fan_id, actual[index].unwrap_or(0), fan_speeds[index]
+                );
+                // Forget the last write so the fan is written again, which also sets GPUFanControlState=1
+                self.last_speeds[index] = None;
+                reassert = true;
+            }
+        }
+        if reassert {
+            self.write_speeds(backend, fan_speeds);
+        }
+    }
+
+    fn check_stalls(&mut self, config: &Config, fan_speeds: &[u32], actual: &[Option<u32>], rpms: &[Option<u32>]) {
+        let now = Instant::now();
+        for (index, &fan_id) in self.gpu.fans.iter().enumerate() {
+            let duty = actual[index].unwrap_or(fan_speeds[index]);
+            let calibrated = config.fan_calibration(&self.gpu, index).map(|fan| calibrate::expected_rpm(fan, duty));
+            match self.stalls.update(index, duty, rpms[index], calibrated, now, &config.stall) {
+                Some(StallEvent::Failed) => {
+                    error!(
+                        "GPU {} fan {} has failed: {} RPM at {}% duty, running the other fans at full speed",
+                        self.gpu.id, fan_id, rpms[index].unwrap_or(0), duty
+                    );
+                    if config.stall.notify {
+                        let summary = format!("GPU {} fan {} failed", self.gpu.id, fan_id);
+                        let body = format!("{} RPM at {}% duty on {}", rpms[index].unwrap_or(0), duty, self.gpu.name);
+                        thread::spawn(move || critical::notify(&summary, &body));
+                    }
+                }
+                Some(StallEvent::Recovered) => info!("GPU {} fan {} is spinning again", self.gpu.id, fan_id),
+                None => {}
+            }
+        }
+    }
+
+    /// Fan speeds the card accepts, the full range if it can't tell
+    fn fan_range(&mut self, backend: &dyn GpuBackend) -> (u32, u32) {
+        let gpu_id = self.gpu.id;
+        *self.fan_range.get_or_insert_with(|| match backend.fan_speed_range(gpu_id) {
+            Ok((min, max)) if min <= max && max <= 100 => {
+                if (min, max) != (0, 100) {
+                    info!("GPU {}: fans accept {}-{}%", gpu_id, min, max);
+                }
+                (min, max)
+            }
+            Ok((min, max)) => {
+                warn!("GPU {}: ignoring implausible fan speed range {}-{}%", gpu_id, min, max);
+                (0, 100)
+            }
+            Err(e) => {
+                log::debug!("GPU {}: fan speed range unknown: {:#}", gpu_id, e);
+                (0, 100)
+            }
+        })
+    }
+
+    /// Decide who runs the fans this tick; true if they stay with the driver.
+    /// A critical step always brings them under our control, and keeps them
+    /// there until it clears, so its power cap is lifted before the driver takes over.
+    fn hybrid_handover(&mut self, backend: &dyn GpuBackend, config: &Config, temp: u32, smoothed: u32) -> bool {
+        let Some(hybrid) = &config.hybrid else {
+            self.driver_in_charge = false;
+            return false;
+        };
+        // A failed fan needs the others at full speed, which the driver won't do
+        let critical = config.critical.iter().any(|step| temp >= step.temp)
+            || self.escalation.level(&config.critical).is_some()
+            || self.stalls.any_failed();
+
+        if self.driver_in_charge && (smoothed >= hybrid.takeover_temp || critical) {
+            info!("GPU {}: {}°C, taking the fans over from the driver", self.gpu.id, smoothed);
+            self.driver_in_charge = false;
+        } else if !self.driver_in_charge && !critical && smoothed + hybrid.hysteresis <= hybrid.takeover_temp {
+            info!("GPU {}: cooled to {}°C, handing the fans back to the driver", self.gpu.id, smoothed);
+            self.release(backend);
+            self.stalls.pause();
+            // Start fresh at the next takeover
+            self.speed_ramp.set(None);
+            self.curve_temp = None;
+            self.pid.reset();
+            self.feed_forward.reset();
+            self.last_target = None;
+            self.driver_in_charge = true;
+        }
+        self.driver_in_charge
+    }
+
+    /// Force the speeds, bypassing the ramp rates, which then carry on from the forced speed.
+    /// With `raise_only`, speeds already above `speed` are left alone.
+    fn override_speeds(&mut self, target_speed: &mut u32, fan_speeds: &mut [u32], speed: u32, raise_only: bool) {
+        let ramps = std::iter::once(&mut self.speed_ramp).chain(&mut self.fan_ramps);
+        let speeds = std::iter::once(target_speed).chain(fan_speeds.iter_mut());
+        for (current, ramp) in speeds.zip(ramps) {
+            if !raise_only || *current < speed {
+                *current = speed;
+                ramp.set(Some(speed as f64));
+            }
+        }
+    }
+
+    /// Read the temperature, rejecting values no working sensor would report
+    fn read_temp(&mut self, backend: &dyn GpuBackend, config: &Config) -> Result<u32, String> {
+        let temp = backend
+            .get_temp(self.gpu.id)
+            .map_err(|e| format!("Failed to read temp: {}", e))?;
+        if temp == 0 || temp >= MAX_PLAUSIBLE_TEMP {
+            return Err(format!("Implausible temperature reading: {}°C", temp));
+        }
+
+        // Judge against the last accepted reading, so dropping back after a spike is fine,
+        // and against a rejected jump, so a real one is accepted on the next tick
+        let max_jump = config.fail_safe.max_jump;
+        let close = |other: Option<u32>| other.is_some_and(|other| temp.abs_diff(other) <= max_jump);
+        let jumped = self.jumped_temp.take();
+        match self.last_temp {
+            Some(previous) if !close(Some(previous)) && !close(jumped) => {
+                self.jumped_temp = Some(temp);
+                Err(format!("Temperature jumped from {}°C to {}°C", previous, temp))
+            }
+            _ => {
+                self.last_temp = Some(temp);
+                Ok(temp)
+            }
+        }
+    }
+
+    fn engage_fail_safe(&mut self, backend: &dyn GpuBackend, config: &Config, status: &Mutex<SharedStatus>, reason: String) {
+        let fail_safe = &config.fail_safe;
+        match fail_safe.action {
+            FailSafeAction::SafeSpeed => {
+                let (min, max) = self.fan_range(backend);
+                let speed = fail_safe.safe_speed.clamp(min, max);
+                warn!("GPU {}: sensor fail-safe engaged after {} bad readings, fans to {}%", self.gpu.id, self.failures, speed);
+                self.write_speeds(backend, &vec![speed; self.gpu.fans.len()]);
+                // In hybrid mode this takes the fans from the driver, so give them back once it's cool again
+                self.driver_in_charge = false;
+            }
+            FailSafeAction::DriverAuto => {
+                warn!("GPU {}: sensor fail-safe engaged after {} bad readings, handing fans to the driver", self.gpu.id, self.failures);
+                self.release(backend);
+                self.driver_in_charge = true;
+            }
+        }
+
+        // Once readings are back the curve takes over directly, without ramping from here
+        self.speed_ramp.set(None);
+        self.fan_ramps.iter_mut().for_each(|ramp| ramp.set(None));
+        self.zero_rpm.reset();
+        self.pid.reset();
+        self.feed_forward.reset();
+
+        self.update_status(status, |gpu_status| gpu_status.fail_safe = Some(reason.clone()));
+        self.fail_safe = Some(reason);
+    }
+
+    fn apply_power_limit(&mut self, backend: &dyn GpuBackend, watts: Option<u32>) {
+        if watts == self.power_limit {
+            return;
+        }
+        match backend.set_power_limit(&self.gpu, watts) {
+            Ok(_) => {
+                match watts {
+                    Some(watts) => warn!("GPU {}: power limit lowered to {} W", self.gpu.id, watts),
+                    None => info!("GPU {}: power limit back to default", self.gpu.id),
+                }
+                self.power_limit = watts;
+            }
+            Err(e) => error!("Failed to set GPU {} power limit: {}", self.gpu.id, e),
+        }
+    }
+
+    fn update_status(&self, status: &Mutex<SharedStatus>, update: impl FnOnce(&mut GpuStatus)) {
+        if let Ok(mut status) = status.lock() {
+            if let Some(gpu_status) = status.gpus.iter_mut().find(|g| g.gpu.id == self.gpu.id) {
+                update(gpu_status);
+            }
+        }
+    }
+
+    /// Write each fan's speed, skipping fans already at that speed
+    fn write_speeds(&mut self, backend: &dyn GpuBackend, fan_speeds: &[u32]) {
+        let gpu_id = self.gpu.id;
+        for (index, &fan_id) in self.gpu.fans.iter().enumerate() {
+            let speed = fan_speeds[index];
+            // Only update if speed changed
+            if self.last_speeds[index] == Some(speed) {
+                continue;
+            }
+            match backend.set_fan_speed(gpu_id, fan_id, speed) {
+                Ok(_) => {
+                    info!("GPU {} fan {} set to {}%", gpu_id, fan_id, speed);
+                    self.last_speeds[index] = Some(speed);
+                    // Give the fan time to get there before judging it
+                    self.drift_since[index] = None;
+                }
+                Err(e) => error!("Failed to set GPU {} fan {} speed: {}", gpu_id, fan_id, e),
+            }
+        }
+    }
+
+    /// Give the fans back to the driver so they don't stay stuck at our last speed
+    fn release(&mut self, backend: &dyn GpuBackend) {
+        if self.gpu.fans.is_empty() {
+            return;
+        }
+        match backend.restore_auto(&self.gpu) {
+            Ok(_) => info!("GPU {} fans returned to driver control", self.gpu.id),
+            Err(e) => error!("Failed to return GPU {} fans to driver control: {}", self.gpu.id, e),
+        }
+        self.last_speeds.iter_mut().for_each(|s| *s = None);
+        self.fan_ramps.iter_mut().for_each(|ramp| ramp.set(None));
+        self.zero_rpm.reset();
+    }
 }
 
 impl DaemonState {
-    pub fn new(curve: Vec<(u32, u32)>) -> Self {
+    pub fn new(config: Config, backend: Arc<dyn GpuBackend>) -> Self {
         Self {
             running: Arc::new(AtomicBool::new(false)),
-            curve: Arc::new(Mutex::new(curve)),
+            config: Arc::new(Mutex::new(config)),
             status: Arc::new(Mutex::new(SharedStatus::default())),
+            backend,
+            worker: Arc::new(Mutex::new(None)),
         }
     }
 
@@ -37,74 +677,732 @@ impl DaemonState {
         
         self.running.store(true, Ordering::Relaxed);
         let running = self.running.clone();
-        let curve_lock = self.curve.clone();
+        let config_lock = self.config.clone();
         let status_lock = self.status.clone(); // Clone for thread
+        let backend = self.backend.clone();
 
-        thread::spawn(move || {
-            info!("Daemon started");
+        let handle = thread::spawn(move || {
+            info!("Daemon started ({} backend)", backend.name());
             
-            // Detect GPU and fans
-            let gpu_id = 0;
-            let fan_count = crate::hardware::probe::count_fans(gpu_id).unwrap_or_else(|e| {
-                error!("Failed to detect fans, assuming 1: {}", e);
-                1
+            // Detect GPUs and fans
+            let gpus = backend.detect_gpus().unwrap_or_else(|e| {
+                error!("Failed to detect GPUs, assuming GPU 0 with 1 fan: {}", e);
+                vec![Gpu { id: 0, name: "Nvidia GPU".to_string(), fans: vec![0], ..Default::default() }]
             });
-            info!("Detected {} fan(s) on GPU {}", fan_count, gpu_id);
-
-            let mut last_speed: Option<u32> = None;
-
-            while running.load(Ordering::Relaxed) {
-                match nvidia::get_temp(gpu_id) {
-                    Ok(temp) => {
-                        let curve = curve_lock.lock().unwrap();
-                        let target_speed = math::calculate_target_speed(temp, &curve);
-                        let usage = nvidia::get_gpu_usage(gpu_id).unwrap_or(0);
-                        
-                        info!("Temp: {}°C, Usage: {}% -> Target Speed: {}%", temp, usage, target_speed);
-                        
-                        // Update Shared Status
-                        if let Ok(mut status) = status_lock.lock() {
-                            status.current_temp = temp;
-                            status.current_speed = target_speed;
-                            status.gpu_usage = usage;
-                        }
+            for gpu in &gpus {
+                info!("GPU {} ({}) drives fan(s) {:?}", gpu.id, gpu.name, gpu.fans);
+            }
+
+            // Per-GPU settings are keyed by UUID/PCI bus ID; resolve them to today's indices
+            if let Ok(config) = config_lock.lock() {
+                for gpu in &gpus {
+                    match config.gpu_config(gpu) {
+                        Some(entry) => info!("GPU {} uses the settings for {}", gpu.id, entry.key()),
+                        None => info!("GPU {} uses the shared curve", gpu.id),
+                    }
+                }
+                for entry in config.missing_gpus(&gpus) {
+                    warn!("Configured GPU {} was not found; its settings will not be applied", entry.key());
+                }
+            }
+
+            if let Ok(mut status) = status_lock.lock() {
+                status.gpus = gpus.iter()
+                    .map(|gpu| GpuStatus { gpu: gpu.clone(), ..Default::default() })
+                    .collect();
+            }
 
-                        // Only update if speed changed
-                        let should_update = match last_speed {
-                            Some(s) => s != target_speed,
-                            None => true,
-                        };
-
-                        if should_update {
-                            // Apply speed to ALL fans
-                            let mut all_success = true;
-                            for fan_id in 0..fan_count {
-                                match nvidia::set_fan_speed(gpu_id, fan_id, target_speed) {
-                                    Ok(_) => info!("Fan {} set to {}%", fan_id, target_speed),
-                                    Err(e) => {
-                                        error!("Failed to set fan {} speed: {}", fan_id, e);
-                                        all_success = false;
-                                    }
-                                }
-                            }
-                            if all_success {
-                                last_speed = Some(target_speed);
-                            }
+            let mut controls: Vec<GpuControl> = gpus.into_iter().map(GpuControl::new).collect();
+            let mut sensors = Sensors::default();
+
+            // A bug in the loop must never leave the fans stuck in manual mode
+            let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                while running.load(Ordering::Relaxed) {
+                    let config = config_lock.lock().unwrap().clone();
+                    sensors.refresh(&config);
+                    for control in &mut controls {
+                        control.tick(&*backend, &config, &sensors, &status_lock);
+                    }
+                    
+                    // Sleep until the next tick, waking early if stop() unparks us
+                    let deadline = Instant::now() + Duration::from_millis(interval_ms);
+                    while running.load(Ordering::Relaxed) {
+                        let now = Instant::now();
+                        if now >= deadline {
+                            break;
                         }
-                    },
-                    Err(e) => {
-                        error!("Failed to read temp from GPU {}: {}", gpu_id, e);
+                        thread::park_timeout(deadline - now);
                     }
                 }
-                
-                thread::sleep(Duration::from_millis(interval_ms));
+            }));
+            if result.is_err() {
+                error!("Control loop panicked, handing the fans back to the driver");
+                running.store(false, Ordering::Relaxed);
+            }
+
+            for control in &mut controls {
+                control.apply_power_limit(&*backend, None);
+                control.release(&*backend);
             }
             info!("Daemon stopped");
         });
+        *self.worker.lock().unwrap() = Some(handle);
     }
 
-    #[allow(dead_code)]
+    /// Stop the control loop and wait until the fans are back under driver control
     pub fn stop(&self) {
         self.running.store(false, Ordering::Relaxed);
+        let handle = self.worker.lock().unwrap().take();
+        if let Some(handle) = handle {
+            handle.thread().unpark();
+            if handle.join().is_err() {
+                error!("Daemon thread panicked");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+    use crate::hardware::probe::Gpu;
+
+    /// Fixed-temperature backend that records every fan write
+    struct MockBackend {
+        /// None makes the sensor read fail
+        temps: Mutex<[Option<u32>; 2]>,
+        writes: Mutex<Vec<(u32, u32, u32)>>,
+        restored: Mutex<Vec<u32>>,
+        power_limits: Mutex<Vec<(u32, Option<u32>)>>,
+        /// Blow up on the second GPU's usage read, like a bug in the loop would
+        panic_on_usage: bool,
+        fan_range: (u32, u32),
+        /// Set when "another tool" put the fans back under the driver, at this speed
+        driver_speed: Mutex<Option<u32>>,
+        /// Fan that reads 0 RPM whatever it's told
+        dead_fan: Mutex<Option<u32>>,
+    }
+
+    impl MockBackend {
+        fn new(temps: [u32; 2]) -> Arc<Self> {
+            Arc::new(Self {
+                temps: Mutex::new(temps.map(Some)),
+                writes: Mutex::new(Vec::new()),
+                restored: Mutex::new(Vec::new()),
+                power_limits: Mutex::new(Vec::new()),
+                panic_on_usage: false,
+                fan_range: (0, 100),
+                driver_speed: Mutex::new(None),
+                dead_fan: Mutex::new(None),
+            })
+        }
+
+        fn set_temp(&self, gpu_id: u32, temp: Option<u32>) {
+            self.temps.lock().unwrap()[gpu_id as usize] = temp;
+        }
+    }
+
+    impl GpuBackend for MockBackend {
+        fn name(&self) -> &'static str {
+            "mock"
+        }
+
+        fn detect_gpus(&self) -> Result<Vec<Gpu>> {
+            Ok(vec![
+                Gpu { id: 0, name: "Mock GPU 0".into(), uuid: Some("GPU-mock-0".into()), fans: vec![0, 1], ..Default::default() },
+                Gpu { id: 1, name: "Mock GPU 1".into(), fans: vec![2], ..Default::default() },
+            ])
+        }
+
+        fn get_temp(&self, gpu_id: u32) -> Result<u32> {
+            self.temps.lock().unwrap()[gpu_id as usize].ok_or_else(|| anyhow::anyhow!("sensor gone"))
+        }
+
+        fn get_gpu_usage(&self, gpu: &Gpu) -> Result<u32> {
+            if self.panic_on_usage && gpu.id == 1 {
+                panic!("simulated bug");
+            }
+            Ok(42)
+        }
+
+        fn get_power_draw(&self, _gpu: &Gpu) -> Result<u32> {
+            Ok(180)
+        }
+
+        fn fan_speed_range(&self, _gpu_id: u32) -> Result<(u32, u32)> {
+            Ok(self.fan_range)
+        }
+
+        fn get_fan_speed(&self, gpu_id: u32, fan_id: u32) -> Result<u32> {
+            if let Some(speed) = *self.driver_speed.lock().unwrap() {
+                return Ok(speed);
+            }
+            self.writes.lock().unwrap().iter()
+                .rev()
+                .find(|(gpu, fan, _)| (*gpu, *fan) == (gpu_id, fan_id))
+                .map(|(_, _, speed)| *speed)
+                .ok_or_else(|| anyhow::anyhow!("never written"))
+        }
+
+        fn get_fan_rpm(&self, gpu_id: u32, fan_id: u32) -> Result<u32> {
+            if *self.dead_fan.lock().unwrap() == Some(fan_id) {
+                return Ok(0);
+            }
+            Ok(self.get_fan_speed(gpu_id, fan_id)? * 20)
+        }
+
+        fn get_memory_temp(&self, _gpu: &Gpu) -> Result<u32> {
+            anyhow::bail!("not reported")
+        }
+
+        fn set_fan_speed(&self, gpu_id: u32, fan_id: u32, speed: u32) -> Result<()> {
+            *self.driver_speed.lock().unwrap() = None;
+            self.writes.lock().unwrap().push((gpu_id, fan_id, speed));
+            Ok(())
+        }
+
+        fn restore_auto(&self, gpu: &Gpu) -> Result<()> {
+            self.restored.lock().unwrap().push(gpu.id);
+            Ok(())
+        }
+
+        fn set_power_limit(&self, gpu: &Gpu, watts: Option<u32>) -> Result<()> {
+            self.power_limits.lock().unwrap().push((gpu.id, watts));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_loop_drives_backend() {
+        let backend = MockBackend::new([40, 50]);
+        let config = Config { curve: vec![(30, 0), (50, 40)], ..Config::default() };
+        let daemon = DaemonState::new(config, backend.clone());
+
+        daemon.start(10);
+        thread::sleep(Duration::from_millis(100));
+        daemon.stop();
+
+        // Speed never changes, so each fan is written exactly once,
+        // and each GPU follows its own temperature
+        assert_eq!(*backend.writes.lock().unwrap(), vec![(0, 0, 20), (0, 1, 20), (1, 2, 40)]);
+
+        let status = daemon.status.lock().unwrap();
+        assert_eq!(status.gpus.len(), 2);
+        assert_eq!(status.gpus[0].current_temp, 40);
+        assert_eq!(status.gpus[0].current_speed, 20);
+        assert_eq!(status.gpus[0].gpu_usage, 42);
+        assert_eq!(status.gpus[1].gpu.name, "Mock GPU 1");
+        assert_eq!(status.gpus[1].current_speed, 40);
+    }
+
+    #[test]
+    fn test_per_fan_overrides() {
+        let backend = MockBackend::new([40, 50]);
+        let config: Config = serde_json::from_str(r#"{
+            "curve": [[30, 0], [50, 40]],
+            "interval_ms": 10,
+            "gpus": [{
+                "uuid": "GPU-mock-0",
+                "fans": [{ "index": 1, "curve": [[30, 50], [50, 90]], "offset": -5 }]
+            }]
+        }"#).unwrap();
+        let daemon = DaemonState::new(config, backend.clone());
+
+        daemon.start(10);
+        thread::sleep(Duration::from_millis(100));
+        daemon.stop();
+
+        // Fan 1 of GPU 0 follows its own curve (70%) minus 5; everything else the shared one
+        assert_eq!(*backend.writes.lock().unwrap(), vec![(0, 0, 20), (0, 1, 65), (1, 2, 40)]);
+        assert_eq!(daemon.status.lock().unwrap().gpus[0].fan_speeds, vec![20, 65]);
+    }
+
+    #[test]
+    fn test_stop_restores_driver_control() {
+        let backend = MockBackend::new([40, 50]);
+        let daemon = DaemonState::new(Config::default(), backend.clone());
+
+        // A long interval must not delay shutdown
+        daemon.start(60_000);
+        thread::sleep(Duration::from_millis(50));
+        let started = Instant::now();
+        daemon.stop();
+
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(!daemon.running.load(Ordering::Relaxed));
+        // stop() joined the worker, so the fans are already released
+        assert_eq!(*backend.restored.lock().unwrap(), vec![0, 1]);
+    }
+
+    #[test]
+    fn test_panic_restores_driver_control() {
+        let mock = Arc::into_inner(MockBackend::new([40, 50])).unwrap();
+        let backend = Arc::new(MockBackend { panic_on_usage: true, ..mock });
+        let daemon = DaemonState::new(Config::default(), backend.clone());
+
+        daemon.start(10);
+        // Printing the panic can take a while with backtraces enabled
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while daemon.running.load(Ordering::Relaxed) && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        // The loop is gone and says so, and every GPU went back to the driver
+        assert!(!daemon.running.load(Ordering::Relaxed));
+        daemon.stop();
+        assert_eq!(*backend.restored.lock().unwrap(), vec![0, 1]);
+    }
+
+    /// Drive one GPU's control directly, tick by tick
+    fn control_gpu0(backend: &MockBackend) -> (GpuControl, Mutex<SharedStatus>) {
+        let gpu = backend.detect_gpus().unwrap().remove(0);
+        let status = SharedStatus { gpus: vec![GpuStatus { gpu: gpu.clone(), ..Default::default() }] };
+        (GpuControl::new(gpu), Mutex::new(status))
+    }
+
+    #[test]
+    fn test_fail_safe_after_read_failures() {
+        let backend = MockBackend::new([40, 50]);
+        let config = Config { curve: vec![(30, 0), (50, 40)], ..Config::default() };
+        let (mut control, status) = control_gpu0(&backend);
+
+        control.tick(&*backend, &config, &Sensors::default(), &status);
+        backend.set_temp(0, None);
+        control.tick(&*backend, &config, &Sensors::default(), &status);
+        control.tick(&*backend, &config, &Sensors::default(), &status);
+        // Two failures keep the last speed
+        assert_eq!(backend.writes.lock().unwrap().len(), 2);
+        assert!(status.lock().unwrap().gpus[0].fail_safe.is_none());
+
+        control.tick(&*backend, &config, &Sensors::default(), &status);
+        assert_eq!(backend.writes.lock().unwrap()[2..], [(0, 0, 100), (0, 1, 100)]);
+        assert!(status.lock().unwrap().gpus[0].fail_safe.is_some());
+
+        // A good reading hands control back to the curve
+        backend.set_temp(0, Some(40));
+        control.tick(&*backend, &config, &Sensors::default(), &status);
+        assert_eq!(backend.writes.lock().unwrap()[4..], [(0, 0, 20), (0, 1, 20)]);
+        assert!(status.lock().unwrap().gpus[0].fail_safe.is_none());
+    }
+
+    #[test]
+    fn test_fail_safe_on_implausible_readings() {
+        let backend = MockBackend::new([40, 50]);
+        let mut config = Config::default();
+        config.fail_safe.max_failures = 2;
+        config.fail_safe.action = FailSafeAction::DriverAuto;
+        let (mut control, status) = control_gpu0(&backend);
+
+        control.tick(&*backend, &config, &Sensors::default(), &status);
+        for temp in [0, 255] {
+            backend.set_temp(0, Some(temp));
+            control.tick(&*backend, &config, &Sensors::default(), &status);
+        }
+        assert_eq!(*backend.restored.lock().unwrap(), vec![0]);
+        assert!(status.lock().unwrap().gpus[0].fail_safe.is_some());
+    }
+
+    #[test]
+    fn test_temperature_jumps() {
+        let backend = MockBackend::new([40, 50]);
+        let config = Config::default();
+        let (mut control, status) = control_gpu0(&backend);
+
+        // A one-tick spike is rejected, the drop back from it is not
+        assert_eq!(control.read_temp(&*backend, &config), Ok(40));
+        backend.set_temp(0, Some(90));
+        assert!(control.read_temp(&*backend, &config).is_err());
+        backend.set_temp(0, Some(40));
+        assert_eq!(control.read_temp(&*backend, &config), Ok(40));
+
+        // A real jump is believed once the next reading agrees
+        backend.set_temp(0, Some(80));
+        assert!(control.read_temp(&*backend, &config).is_err());
+        backend.set_temp(0, Some(81));
+        assert_eq!(control.read_temp(&*backend, &config), Ok(81));
+        control.tick(&*backend, &config, &Sensors::default(), &status);
+        assert!(status.lock().unwrap().gpus[0].fail_safe.is_none());
+
+        // One glitch counts as one failure, so it can't trip a fail-safe that needs two
+        let mut config = Config::default();
+        config.fail_safe.max_failures = 2;
+        for temp in [81, 20, 81, 81] {
+            backend.set_temp(0, Some(temp));
+            control.tick(&*backend, &config, &Sensors::default(), &status);
+        }
+        assert!(status.lock().unwrap().gpus[0].fail_safe.is_none());
+    }
+
+    #[test]
+    fn test_critical_ladder_overrides_curve() {
+        let backend = MockBackend::new([40, 50]);
+        // Every slider dragged to zero
+        let config: Config = serde_json::from_str(r#"{
+            "curve": [[30, 0], [85, 0]],
+            "interval_ms": 10,
+            "critical": [
+                { "temp": 88, "fan_speed": 100 },
+                { "temp": 92, "power_limit": 150 }
+            ]
+        }"#).unwrap();
+        let (mut control, status) = control_gpu0(&backend);
+
+        control.tick(&*backend, &config, &Sensors::default(), &status);
+        backend.set_temp(0, Some(60));
+        control.tick(&*backend, &config, &Sensors::default(), &status);
+        backend.set_temp(0, Some(80));
+        control.tick(&*backend, &config, &Sensors::default(), &status);
+        backend.set_temp(0, Some(89));
+        control.tick(&*backend, &config, &Sensors::default(), &status);
+        assert_eq!(status.lock().unwrap().gpus[0].fan_speeds, vec![100, 100]);
+        assert_eq!(status.lock().unwrap().gpus[0].critical, Some(88));
+        assert!(backend.power_limits.lock().unwrap().is_empty());
+
+        backend.set_temp(0, Some(93));
+        control.tick(&*backend, &config, &Sensors::default(), &status);
+        assert_eq!(*backend.power_limits.lock().unwrap(), vec![(0, Some(150))]);
+
+        // Cooling well below both steps drops the limit and the forced speed
+        backend.set_temp(0, Some(80));
+        control.tick(&*backend, &config, &Sensors::default(), &status);
+        assert_eq!(*backend.power_limits.lock().unwrap(), vec![(0, Some(150)), (0, None)]);
+        assert_eq!(status.lock().unwrap().gpus[0].fan_speeds, vec![0, 0]);
+        assert_eq!(status.lock().unwrap().gpus[0].critical, None);
+    }
+
+    #[test]
+    fn test_zero_rpm_overrides_curve() {
+        let backend = MockBackend::new([40, 50]);
+        let config: Config = serde_json::from_str(r#"{
+            "curve": [[30, 0], [50, 40], [70, 80]],
+            "interval_ms": 10,
+            "zero_rpm": { "stop_temp": 45, "start_temp": 55, "kick_speed": 60, "kick_secs": 60 }
+        }"#).unwrap();
+        let (mut control, status) = control_gpu0(&backend);
+
+        // The curve asks for 20%, but the GPU is in the zero-RPM region
+        control.tick(&*backend, &config, &Sensors::default(), &status);
+        assert_eq!(status.lock().unwrap().gpus[0].fan_speeds, vec![0, 0]);
+
+        // Starting from standstill kicks the fans above the curve's 50%
+        backend.set_temp(0, Some(55));
+        control.tick(&*backend, &config, &Sensors::default(), &status);
+        assert_eq!(status.lock().unwrap().gpus[0].fan_speeds, vec![60, 60]);
+        assert_eq!(*backend.writes.lock().unwrap(), vec![(0, 0, 0), (0, 1, 0), (0, 0, 60), (0, 1, 60)]);
+    }
+
+    #[test]
+    fn test_pid_mode_replaces_curves() {
+        let backend = MockBackend::new([40, 50]);
+        let config: Config = serde_json::from_str(r#"{
+            "curve": [[30, 90], [50, 90]],
+            "interval_ms": 10,
+            "mode": "pid",
+            "pid": { "target_temp": 70, "min_speed": 25 },
+            "gpus": [{ "uuid": "GPU-mock-0", "fans": [{ "index": 1, "curve": [[30, 100]], "offset": 5 }] }]
+        }"#).unwrap();
+        let (mut control, status) = control_gpu0(&backend);
+
+        // Well below target: the controller rests at its minimum, per-fan offsets still apply
+        control.tick(&*backend, &config, &Sensors::default(), &status);
+        let status = status.lock().unwrap();
+        assert_eq!(status.gpus[0].fan_speeds, vec![25, 30]);
+        assert_eq!(status.gpus[0].pid_target, Some(70));
+    }
+
+    #[test]
+    fn test_curves_on_other_inputs() {
+        // The mock draws 180 W at 42% usage and reports no memory temperature
+        let backend = MockBackend::new([40, 50]);
+        let config: Config = serde_json::from_str(r#"{
+            "curve": [[30, 0], [50, 40]],
+            "interval_ms": 10,
+            "extra_curves": [
+                { "input": "memory_temp", "curve": [[30, 100]] },
+                { "input": "usage", "curve": [[0, 0], [100, 100]] }
+            ],
+            "gpus": [{
+                "uuid": "GPU-mock-0",
+                "input": "power_draw",
+                "fans": [{ "index": 1, "curve": [[100, 20], [200, 80]] }]
+            }]
+        }"#).unwrap();
+        let (mut control, status) = control_gpu0(&backend);
+
+        // Fan 0: the shared curve read at 180 W tops out at 40%, so the usage curve's 42% wins.
+        // Fan 1: 68% from its own power curve. The unavailable memory curve is skipped.
+        control.tick(&*backend, &config, &Sensors::default(), &status);
+        assert_eq!(status.lock().unwrap().gpus[0].fan_speeds, vec![42, 68]);
+    }
+
+    #[test]
+    fn test_missing_input_falls_back_to_core_temp_curve() {
+        // The mock reports no memory temperature; GPU 0 is at 40°C
+        let backend = MockBackend::new([40, 50]);
+        let mut config: Config = serde_json::from_str(r#"{
+            "curve": [[30, 0], [50, 40]],
+            "interval_ms": 10,
+            "gpus": [{ "uuid": "GPU-mock-0", "input": "memory_temp", "curve": [[0, 100]] }]
+        }"#).unwrap();
+        let (mut control, status) = control_gpu0(&backend);
+
+        // The memory curve isn't read at 40 as if it were °C of something else;
+        // the shared core temperature curve takes over
+        control.tick(&*backend, &config, &Sensors::default(), &status);
+        assert_eq!(status.lock().unwrap().gpus[0].fan_speeds, vec![20, 20]);
+
+        // No core temperature curve configured at all: the default one
+        config.input = CurveInput::PowerDraw;
+        config.gpus[0].input = None;
+        config.gpus[0].fans = serde_json::from_str(r#"[{ "index": 1, "input": "memory_temp" }]"#).unwrap();
+        control.tick(&*backend, &config, &Sensors::default(), &status);
+        assert_eq!(status.lock().unwrap().gpus[0].fan_speeds[1], 15);
+    }
+
+    #[test]
+    fn test_gpu_fans_follow_external_sensor() {
+        let path = std::env::temp_dir().join(format!("nvidia-wormhole-cpu-{}", std::process::id()));
+        std::fs::write(&path, "85\n").unwrap();
+        let backend = MockBackend::new([40, 50]);
+        let config: Config = serde_json::from_str(&format!(r#"{{
+            "curve": [[30, 0], [50, 40]],
+            "interval_ms": 10,
+            "sensors": [{{ "name": "cpu", "type": "file", "path": "{}" }}],
+            "extra_curves": [{{ "input": {{ "sensor": "cpu" }}, "curve": [[60, 30], [90, 90]] }}]
+        }}"#, path.display())).unwrap();
+        let (mut control, status) = control_gpu0(&backend);
+
+        // A hot CPU raises the GPU's fans past its own curve
+        control.tick(&*backend, &config, &Sensors::default(), &status);
+        assert_eq!(status.lock().unwrap().gpus[0].fan_speeds, vec![80, 80]);
+
+        // Once the sensor is gone the GPU's curve carries on alone
+        std::fs::remove_file(&path).unwrap();
+        control.tick(&*backend, &config, &Sensors::default(), &status);
+        assert_eq!(status.lock().unwrap().gpus[0].fan_speeds, vec![20, 20]);
+    }
+
+    #[test]
+    fn test_formula_mode_falls_back_to_curves() {
+        let backend = MockBackend::new([40, 50]);
+        let mut config: Config = serde_json::from_str(r#"{
+            "curve": [[30, 0], [50, 40]],
+            "interval_ms": 10,
+            "mode": "formula",
+            "formula": { "expression": "let next = prev + usage / 2; next > 50 ? 200 : next" },
+            "gpus": [{ "uuid": "GPU-mock-0", "fans": [{ "index": 1, "offset": 5 }] }]
+        }"#).unwrap();
+        let (mut control, status) = control_gpu0(&backend);
+        let fan_speeds = |status: &Mutex<SharedStatus>| status.lock().unwrap().gpus[0].fan_speeds.clone();
+
+        // Usage is 42%, so each tick adds 21% to the previous speed
+        control.tick(&*backend, &config, &Sensors::default(), &status);
+        assert_eq!(fan_speeds(&status), vec![21, 26]);
+        control.tick(&*backend, &config, &Sensors::default(), &status);
+        assert_eq!(fan_speeds(&status), vec![42, 47]);
+
+        // 200% is out of range, so the curve (20% at 40°C) stands in
+        control.tick(&*backend, &config, &Sensors::default(), &status);
+        assert_eq!(fan_speeds(&status), vec![20, 25]);
+
+        config.formula.expression = "usage +".into();
+        control.tick(&*backend, &config, &Sensors::default(), &status);
+        assert_eq!(fan_speeds(&status), vec![20, 25]);
+    }
+
+    #[test]
+    fn test_hybrid_mode_hands_over_with_hysteresis() {
+        let backend = MockBackend::new([60, 50]);
+        let mut config: Config = serde_json::from_str(r#"{
+            "curve": [[60, 40], [80, 80]],
+            "interval_ms": 10,
+            "hybrid": { "takeover_temp": 75, "hysteresis": 5 }
+        }"#).unwrap();
+        let (mut control, status) = control_gpu0(&backend);
+        let tick = |control: &mut GpuControl, config: &Config, temp| {
+            backend.set_temp(0, Some(temp));
+            control.tick(&*backend, config, &Sensors::default(), &status);
+        };
+
+        // Cool: the driver keeps the fans and nothing is written
+        tick(&mut control, &config, 60);
+        assert!(backend.writes.lock().unwrap().is_empty());
+        assert_eq!(status.lock().unwrap().gpus[0].driver_until, Some(75));
+
+        tick(&mut control, &config, 76);
+        assert_eq!(*backend.writes.lock().unwrap(), vec![(0, 0, 72), (0, 1, 72)]);
+        assert_eq!(status.lock().unwrap().gpus[0].driver_until, None);
+
+        // Inside the hysteresis band we keep control, at 70°C the driver gets it back
+        tick(&mut control, &config, 72);
+        assert!(backend.restored.lock().unwrap().is_empty());
+        tick(&mut control, &config, 70);
+        assert_eq!(*backend.restored.lock().unwrap(), vec![0]);
+        assert_eq!(status.lock().unwrap().gpus[0].driver_until, Some(75));
+
+        // A critical step takes over even below the takeover temperature
+        config.hybrid.as_mut().unwrap().takeover_temp = 95;
+        backend.writes.lock().unwrap().clear();
+        tick(&mut control, &config, 89);
+        assert_eq!(*backend.writes.lock().unwrap(), vec![(0, 0, 100), (0, 1, 100)]);
+    }
+
+    #[test]
+    fn test_hybrid_mode_releases_fans_after_fail_safe() {
+        let backend = MockBackend::new([60, 50]);
+        let config: Config = serde_json::from_str(r#"{
+            "curve": [[60, 40], [80, 80]],
+            "interval_ms": 10,
+            "hybrid": { "takeover_temp": 75, "hysteresis": 5 }
+        }"#).unwrap();
+        let (mut control, status) = control_gpu0(&backend);
+        let tick = |control: &mut GpuControl, temp| {
+            backend.set_temp(0, temp);
+            control.tick(&*backend, &config, &Sensors::default(), &status);
+        };
+
+        // The driver has the fans when the sensor drops out, and the safe speed takes them over
+        tick(&mut control, Some(60));
+        for _ in 0..3 {
+            tick(&mut control, None);
+        }
+        assert_eq!(*backend.writes.lock().unwrap(), vec![(0, 0, 100), (0, 1, 100)]);
+        assert!(backend.restored.lock().unwrap().is_empty());
+
+        // Readings are back and the card is cool: the driver gets the fans again
+        tick(&mut control, Some(60));
+        assert_eq!(*backend.restored.lock().unwrap(), vec![0]);
+        assert_eq!(status.lock().unwrap().gpus[0].driver_until, Some(75));
+    }
+
+    #[test]
+    fn test_hybrid_mode_waits_for_critical_steps_to_clear() {
+        let backend = MockBackend::new([80, 50]);
+        let config: Config = serde_json::from_str(r#"{
+            "curve": [[30, 0], [50, 40]],
+            "interval_ms": 10,
+            "hybrid": { "takeover_temp": 85, "hysteresis": 5 },
+            "critical": [{ "temp": 88, "power_limit": 200 }]
+        }"#).unwrap();
+        let (mut control, status) = control_gpu0(&backend);
+        let tick = |control: &mut GpuControl, temp| {
+            backend.set_temp(0, Some(temp));
+            control.tick(&*backend, &config, &Sensors::default(), &status);
+        };
+
+        tick(&mut control, 80);
+        tick(&mut control, 89);
+        assert_eq!(*backend.power_limits.lock().unwrap(), vec![(0, Some(200))]);
+
+        // Cool enough to hand back, but the step is still active: lift its cap first
+        tick(&mut control, 80);
+        assert!(backend.restored.lock().unwrap().is_empty());
+        assert_eq!(*backend.power_limits.lock().unwrap(), vec![(0, Some(200)), (0, None)]);
+        tick(&mut control, 80);
+        assert_eq!(*backend.restored.lock().unwrap(), vec![0]);
+    }
+
+    #[test]
+    fn test_speeds_stay_in_device_range() {
+        let mock = Arc::into_inner(MockBackend::new([40, 50])).unwrap();
+        let backend = Arc::new(MockBackend { fan_range: (30, 90), ..mock });
+        let config = Config { curve: vec![(30, 0), (50, 40)], ..Config::default() };
+        let (mut control, status) = control_gpu0(&backend);
+
+        // 20% from the curve is below what the card runs at
+        control.tick(&*backend, &config, &Sensors::default(), &status);
+        assert_eq!(*backend.writes.lock().unwrap(), vec![(0, 0, 30), (0, 1, 30)]);
+        assert_eq!(status.lock().unwrap().gpus[0].current_speed, 30);
+
+        // Even the critical ladder's 100% is capped at the card's maximum
+        backend.set_temp(0, Some(60));
+        control.tick(&*backend, &config, &Sensors::default(), &status);
+        backend.set_temp(0, Some(85));
+        control.tick(&*backend, &config, &Sensors::default(), &status);
+        backend.set_temp(0, Some(90));
+        control.tick(&*backend, &config, &Sensors::default(), &status);
+        assert_eq!(status.lock().unwrap().gpus[0].fan_speeds, vec![90, 90]);
+    }
+
+    #[test]
+    fn test_calibrated_fans_run_from_their_start_duty() {
+        let backend = MockBackend::new([40, 50]);
+        let config: Config = serde_json::from_str(r#"{
+            "curve": [[30, 0], [50, 40]],
+            "interval_ms": 10,
+            "gpus": [{ "uuid": "GPU-mock-0", "fans": [
+                { "index": 1, "calibration": { "start_duty": 30, "rpm": [[0, 0], [30, 600], [100, 2000]] } }
+            ] }]
+        }"#).unwrap();
+        let (mut control, status) = control_gpu0(&backend);
+
+        // 20% would leave the calibrated fan standing still
+        control.tick(&*backend, &config, &Sensors::default(), &status);
+        let status = status.lock().unwrap();
+        assert_eq!(status.gpus[0].fan_speeds, vec![20, 30]);
+        // It reaches the RPM it was calibrated at, so it is not taken for stalled
+        assert_eq!(status.gpus[0].fan_rpms, vec![Some(400), Some(600)]);
+        assert_eq!(status.gpus[0].failed_fans, vec![false, false]);
+    }
+
+    #[test]
+    fn test_drift_reasserts_control() {
+        let backend = MockBackend::new([40, 50]);
+        let mut config = Config { curve: vec![(30, 0), (50, 40)], ..Config::default() };
+        let (mut control, status) = control_gpu0(&backend);
+
+        control.tick(&*backend, &config, &Sensors::default(), &status);
+        {
+            let status = status.lock().unwrap();
+            assert_eq!(status.gpus[0].actual_speeds, vec![Some(20), Some(20)]);
+            assert_eq!(status.gpus[0].fan_rpms, vec![Some(400), Some(400)]);
+        }
+
+        // The driver took over behind our back; within the grace period we only report it
+        *backend.driver_speed.lock().unwrap() = Some(45);
+        control.tick(&*backend, &config, &Sensors::default(), &status);
+        assert_eq!(backend.writes.lock().unwrap().len(), 2);
+        {
+            let status = status.lock().unwrap();
+            assert_eq!(status.gpus[0].fan_speeds, vec![20, 20]);
+            assert_eq!(status.gpus[0].actual_speeds, vec![Some(45), Some(45)]);
+        }
+
+        config.verify.grace_secs = 0;
+        control.tick(&*backend, &config, &Sensors::default(), &status);
+        assert_eq!(backend.writes.lock().unwrap()[2..], [(0, 0, 20), (0, 1, 20)]);
+
+        // Small differences are within tolerance
+        *backend.driver_speed.lock().unwrap() = Some(28);
+        control.tick(&*backend, &config, &Sensors::default(), &status);
+        assert_eq!(backend.writes.lock().unwrap().len(), 4);
+    }
+
+    #[test]
+    fn test_stalled_fan_pushes_the_others() {
+        let backend = MockBackend::new([40, 50]);
+        let mut config = Config { curve: vec![(30, 50)], ..Config::default() };
+        config.stall.grace_secs = 0;
+        config.stall.notify = false;
+        let (mut control, status) = control_gpu0(&backend);
+
+        control.tick(&*backend, &config, &Sensors::default(), &status);
+        assert_eq!(status.lock().unwrap().gpus[0].failed_fans, vec![false, false]);
+
+        // Fan 1 dies: flagged on the next read-back, the card's fans go to full speed after that
+        *backend.dead_fan.lock().unwrap() = Some(1);
+        control.tick(&*backend, &config, &Sensors::default(), &status);
+        assert_eq!(status.lock().unwrap().gpus[0].failed_fans, vec![false, true]);
+        control.tick(&*backend, &config, &Sensors::default(), &status);
+        assert_eq!(status.lock().unwrap().gpus[0].fan_speeds, vec![100, 100]);
+
+        // Once it spins again the curve is back in charge
+        *backend.dead_fan.lock().unwrap() = None;
+        control.tick(&*backend, &config, &Sensors::default(), &status);
+        control.tick(&*backend, &config, &Sensors::default(), &status);
+        let status = status.lock().unwrap();
+        assert_eq!(status.gpus[0].failed_fans, vec![false, false]);
+        assert_eq!(status.gpus[0].fan_speeds, vec![50, 50]);
     }
 }
@@ -1,43 +1,323 @@
-/// Linear interpolation for Fan Curve
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use crate::config::{FeedForwardConfig, Interpolation, PidConfig, RampConfig, TempFilter, ZeroRpmConfig};
+
+/// Fan speed for a temperature, rounded to whole percent
 /// Points are (Temperature, FanSpeed%)
 /// Example: [(30, 0), (50, 30), (70, 80), (80, 100)]
-pub fn calculate_target_speed(current_temp: u32, curve: &[(u32, u32)]) -> u32 {
-    if curve.is_empty() {
-        return 0;
+pub fn calculate_target_speed(current_temp: u32, curve: &[(u32, u32)], mode: Interpolation) -> u32 {
+    interpolate(current_temp as f64, curve, mode).round().clamp(0.0, 100.0) as u32
+}
+
+/// Evaluate a curve at any temperature. Outside the points the curve is flat.
+pub fn interpolate(temp: f64, curve: &[(u32, u32)], mode: Interpolation) -> f64 {
+    // Sort by temperature; for duplicate temperatures the last point wins
+    let mut points: Vec<(f64, f64)> = Vec::with_capacity(curve.len());
+    let mut sorted = curve.to_vec();
+    sorted.sort_by_key(|k| k.0);
+    for (t, speed) in sorted {
+        match points.last_mut() {
+            Some(last) if last.0 == t as f64 => last.1 = speed as f64,
+            _ => points.push((t as f64, speed as f64)),
+        }
+    }
+
+    let (Some(&first), Some(&last)) = (points.first(), points.last()) else {
+        return 0.0;
+    };
+    if temp <= first.0 {
+        return first.1;
+    }
+    if temp >= last.0 {
+        return last.1;
+    }
+
+    // Segment k spans points[k]..points[k + 1]
+    let k = points.windows(2).position(|w| temp < w[1].0).unwrap_or(points.len() - 2);
+    let ((x1, y1), (x2, y2)) = (points[k], points[k + 1]);
+    match mode {
+        Interpolation::Step => y1,
+        Interpolation::Linear => y1 + (temp - x1) * (y2 - y1) / (x2 - x1),
+        Interpolation::MonotoneCubic => {
+            let tangents = monotone_tangents(&points);
+            let h = x2 - x1;
+            let t = (temp - x1) / h;
+            let (t2, t3) = (t * t, t * t * t);
+            // Cubic Hermite basis
+            y1 * (2.0 * t3 - 3.0 * t2 + 1.0)
+                + tangents[k] * h * (t3 - 2.0 * t2 + t)
+                + y2 * (-2.0 * t3 + 3.0 * t2)
+                + tangents[k + 1] * h * (t3 - t2)
+        }
+    }
+}
+
+/// Fritsch-Carlson tangents: the Hermite spline through the points never overshoots them
+fn monotone_tangents(points: &[(f64, f64)]) -> Vec<f64> {
+    let n = points.len();
+    let secants: Vec<f64> = points.windows(2).map(|w| (w[1].1 - w[0].1) / (w[1].0 - w[0].0)).collect();
+
+    let mut tangents = vec![0.0; n];
+    tangents[0] = secants[0];
+    tangents[n - 1] = secants[n - 2];
+    for i in 1..n - 1 {
+        // Flat at local extremes and plateaus
+        tangents[i] = if secants[i - 1] * secants[i] <= 0.0 {
+            0.0
+        } else {
+            (secants[i - 1] + secants[i]) / 2.0
+        };
+    }
+
+    for (i, &secant) in secants.iter().enumerate() {
+        if secant == 0.0 {
+            tangents[i] = 0.0;
+            tangents[i + 1] = 0.0;
+            continue;
+        }
+        let alpha = tangents[i] / secant;
+        let beta = tangents[i + 1] / secant;
+        let norm = alpha * alpha + beta * beta;
+        if norm > 9.0 {
+            let tau = 3.0 / norm.sqrt();
+            tangents[i] = tau * alpha * secant;
+            tangents[i + 1] = tau * beta * secant;
+        }
+    }
+    tangents
+}
+
+/// Shift a fan speed by a signed percentage, staying within 0-100%
+pub fn apply_offset(speed: u32, offset: i32) -> u32 {
+    (speed as i32 + offset).clamp(0, 100) as u32
+}
+
+/// Temperature to evaluate the curve at, given the one used last tick.
+/// Rises are followed at once; falls only count once the GPU is `hysteresis` °C below
+/// the temperature that raised the speed, and then trail the real reading by that much.
+pub fn hysteresis_temp(current_temp: u32, previous: Option<u32>, hysteresis: u32) -> u32 {
+    match previous {
+        Some(previous) => current_temp.max(previous.min(current_temp + hysteresis)),
+        None => current_temp,
+    }
+}
+
+/// Keep the last speed when the new target is within `deadband` percent of it.
+/// Full stop and full speed are always let through.
+pub fn apply_deadband(target: u32, last: Option<u32>, deadband: u32) -> u32 {
+    match last {
+        Some(last) if target != 0 && target != 100 && target.abs_diff(last) < deadband => last,
+        _ => target,
+    }
+}
+
+/// Temperature filter state for one GPU
+#[derive(Default)]
+pub struct Smoother {
+    ema: Option<f64>,
+    samples: VecDeque<f64>,
+}
+
+impl Smoother {
+    /// Feed a reading and return the filtered temperature
+    pub fn update(&mut self, temp: u32, filter: &TempFilter) -> f64 {
+        let temp = temp as f64;
+        match *filter {
+            TempFilter::None => temp,
+            TempFilter::Ema { alpha } => {
+                let alpha = alpha.clamp(0.01, 1.0);
+                let smoothed = match self.ema {
+                    Some(previous) => previous + alpha * (temp - previous),
+                    None => temp,
+                };
+                self.ema = Some(smoothed);
+                smoothed
+            }
+            TempFilter::MovingAverage { samples } => {
+                self.samples.push_back(temp);
+                while self.samples.len() > samples.max(1) {
+                    self.samples.pop_front();
+                }
+                self.samples.iter().sum::<f64>() / self.samples.len() as f64
+            }
+        }
+    }
+}
+
+/// Rate-limited speed for one fan
+#[derive(Default)]
+pub struct Ramp {
+    speed: Option<f64>,
+}
+
+impl Ramp {
+    /// Move towards `target`, at most as far as the ramp rates allow in `dt_secs`
+    pub fn step(&mut self, target: f64, rates: &RampConfig, dt_secs: f64) -> f64 {
+        let speed = match self.speed {
+            Some(current) if target > current => match rates.up {
+                Some(rate) => target.min(current + rate * dt_secs),
+                None => target,
+            },
+            Some(current) if target < current => match rates.down {
+                Some(rate) => target.max(current - rate * dt_secs),
+                None => target,
+            },
+            _ => target,
+        };
+        self.speed = Some(speed);
+        speed
+    }
+
+    /// Jump straight to a speed set from outside the ramp, e.g. a safety override
+    pub fn set(&mut self, speed: Option<f64>) {
+        self.speed = speed;
     }
+}
+
+/// PID controller state for one GPU
+#[derive(Default)]
+pub struct Pid {
+    integral: f64,
+    previous_temp: Option<f64>,
+}
+
+impl Pid {
+    /// Fan speed in percent for a new reading taken `dt_secs` after the last one
+    pub fn update(&mut self, temp: f64, config: &PidConfig, dt_secs: f64) -> f64 {
+        let max = config.max_speed.min(100) as f64;
+        let min = (config.min_speed as f64).min(max);
+        let error = temp - config.target_temp as f64;
 
-    // 1. Sort points by temp (x-axis) just to be safe
-    let mut sorted_curve = curve.to_vec();
-    sorted_curve.sort_by_key(|k| k.0);
+        // Derivative of the measurement, not the error, so editing the target doesn't kick
+        let derivative = match self.previous_temp {
+            Some(previous) if dt_secs > 0.0 => (temp - previous) / dt_secs,
+            _ => 0.0,
+        };
+        self.previous_temp = Some(temp);
 
-    // 2. Handle below min
-    if current_temp <= sorted_curve.first().unwrap().0 {
-        return sorted_curve.first().unwrap().1;
+        // Anti-windup: only integrate while the output isn't pinned in the same direction,
+        // and never let the integral term alone leave the output range
+        let unclamped = config.kp * error + config.ki * self.integral + config.kd * derivative;
+        let saturated = (unclamped >= max && error > 0.0) || (unclamped <= min && error < 0.0);
+        if !saturated {
+            self.integral += error * dt_secs;
+        }
+        if config.ki > 0.0 {
+            self.integral = self.integral.clamp(min / config.ki, max / config.ki);
+        }
+
+        let output = config.kp * error + config.ki * self.integral + config.kd * derivative;
+        output.clamp(min, max)
     }
 
-    // 3. Handle above max
-    if current_temp >= sorted_curve.last().unwrap().0 {
-        return sorted_curve.last().unwrap().1;
+    pub fn reset(&mut self) {
+        *self = Self::default();
     }
+}
 
-    // 4. Find the range [p1, p2] where p1.temp <= current_temp < p2.temp
-    for window in sorted_curve.windows(2) {
-        let p1 = window[0];
-        let p2 = window[1];
+/// Feed-forward state for one GPU
+#[derive(Default)]
+pub struct FeedForward {
+    boost: f64,
+    previous_temp: Option<f64>,
+    previous_usage: Option<u32>,
+}
 
-        if current_temp >= p1.0 && current_temp < p2.0 {
-            // Interpolate
-            // y = y1 + (x - x1) * (y2 - y1) / (x2 - x1)
-            let x_range = p2.0 - p1.0;
-            let y_range = p2.1 as i32 - p1.1 as i32; // Use i32 to handle negative slopes? (Unlikely for fans but good practice)
-            let x_diff = current_temp - p1.0;
+impl FeedForward {
+    /// Boost in percent for a new reading taken `dt_secs` after the last one.
+    /// Rising temperature or a jump in utilization raise it at once; it then decays exponentially.
+    pub fn update(&mut self, temp: f64, usage: Option<u32>, config: &FeedForwardConfig, dt_secs: f64) -> f64 {
+        if config.decay_secs > 0.0 {
+            self.boost *= (-dt_secs / config.decay_secs).exp();
+        } else {
+            self.boost = 0.0;
+        }
+
+        if let Some(previous) = self.previous_temp {
+            if dt_secs > 0.0 && temp > previous {
+                self.boost = self.boost.max(config.slope_gain * (temp - previous) / dt_secs);
+            }
+        }
+        self.previous_temp = Some(temp);
 
-            let interpolated = p1.1 as i32 + (x_diff as i32 * y_range / x_range as i32);
-            return interpolated.clamp(0, 100) as u32;
+        // A failed usage read keeps the old value, so recovering from it isn't a jump
+        if let Some(usage) = usage {
+            if let Some(previous) = self.previous_usage {
+                let jump = usage.saturating_sub(previous);
+                if jump >= config.usage_jump.max(1) {
+                    self.boost = self.boost.max(config.usage_gain * jump as f64);
+                }
+            }
+            self.previous_usage = Some(usage);
         }
+
+        self.boost = self.boost.clamp(0.0, config.max_boost.min(100) as f64);
+        self.boost
     }
 
-    0 // Should be unreachable
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
+/// What zero-RPM mode wants from the fans this tick
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ZeroRpmAction {
+    /// Fans off, whatever the curve says
+    Stop,
+    /// Just started: at least this speed so the fans actually spin up
+    Kick(u32),
+    /// Running, the curve decides
+    Curve,
+}
+
+/// Zero-RPM state for one GPU
+#[derive(Default)]
+pub struct ZeroRpm {
+    /// When the fans were last started, None while stopped
+    started: Option<Instant>,
+    initialized: bool,
+}
+
+impl ZeroRpm {
+    pub fn update(&mut self, temp: u32, now: Instant, config: &ZeroRpmConfig) -> ZeroRpmAction {
+        let start_temp = config.start_temp.max(config.stop_temp + 1);
+
+        // Without history, only a GPU at the start temperature needs its fans
+        if !self.initialized {
+            self.initialized = true;
+            if temp < start_temp {
+                return ZeroRpmAction::Stop;
+            }
+        }
+
+        match self.started {
+            None if temp >= start_temp => {
+                self.started = Some(now);
+            }
+            None => return ZeroRpmAction::Stop,
+            Some(started) => {
+                let min_on = Duration::from_secs(config.min_on_secs);
+                if temp <= config.stop_temp && now.duration_since(started) >= min_on {
+                    self.started = None;
+                    return ZeroRpmAction::Stop;
+                }
+            }
+        }
+
+        let started = self.started.unwrap_or(now);
+        if now.duration_since(started) < Duration::from_secs(config.kick_secs) {
+            ZeroRpmAction::Kick(config.kick_speed.min(100))
+        } else {
+            ZeroRpmAction::Curve
+        }
+    }
+
+    /// Forget the state, e.g. after the driver had the fans
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
 }
 
 #[cfg(test)]
@@ -49,25 +329,301 @@ mod tests {
         let curve = vec![(30, 0), (50, 40), (80, 100)];
         
         // Exact points
-        assert_eq!(calculate_target_speed(30, &curve), 0);
-        assert_eq!(calculate_target_speed(50, &curve), 40);
-        assert_eq!(calculate_target_speed(80, &curve), 100);
+        assert_eq!(calculate_target_speed(30, &curve, Interpolation::Linear), 0);
+        assert_eq!(calculate_target_speed(50, &curve, Interpolation::Linear), 40);
+        assert_eq!(calculate_target_speed(80, &curve, Interpolation::Linear), 100);
         
         // Below min
-        assert_eq!(calculate_target_speed(20, &curve), 0);
+        assert_eq!(calculate_target_speed(20, &curve, Interpolation::Linear), 0);
         
         // Above max
-        assert_eq!(calculate_target_speed(90, &curve), 100);
+        assert_eq!(calculate_target_speed(90, &curve, Interpolation::Linear), 100);
         
         // Midpoint (Linear)
         // Between 30 (0%) and 50 (40%) at temp 40:
         // Range X=20, Y=40. Ratio = 2.
         // Diff = 10. Result = 0 + 10*2 = 20.
-        assert_eq!(calculate_target_speed(40, &curve), 20);
+        assert_eq!(calculate_target_speed(40, &curve, Interpolation::Linear), 20);
         
         // Between 50 (40%) and 80 (100%) at temp 65:
         // Range X=30, Y=60. Ratio = 2.
         // Diff = 15. Result = 40 + 15*2 = 70.
-        assert_eq!(calculate_target_speed(65, &curve), 70);
+        assert_eq!(calculate_target_speed(65, &curve, Interpolation::Linear), 70);
+    }
+
+    #[test]
+    fn test_linear_rounds() {
+        // 25% of the way from 0% to 30%: 7.5% rounds up instead of truncating to 7
+        let curve = vec![(40, 0), (60, 30)];
+        assert_eq!(calculate_target_speed(45, &curve, Interpolation::Linear), 8);
+        // Unsorted points and duplicate temperatures are fine
+        let messy = vec![(60, 30), (40, 10), (40, 0)];
+        assert_eq!(calculate_target_speed(45, &messy, Interpolation::Linear), 8);
+    }
+
+    #[test]
+    fn test_step() {
+        let curve = vec![(30, 0), (50, 40), (80, 100)];
+        assert_eq!(calculate_target_speed(20, &curve, Interpolation::Step), 0);
+        assert_eq!(calculate_target_speed(49, &curve, Interpolation::Step), 0);
+        assert_eq!(calculate_target_speed(50, &curve, Interpolation::Step), 40);
+        assert_eq!(calculate_target_speed(79, &curve, Interpolation::Step), 40);
+        assert_eq!(calculate_target_speed(80, &curve, Interpolation::Step), 100);
+    }
+
+    #[test]
+    fn test_monotone_cubic() {
+        let curve = vec![(30, 0), (50, 30), (70, 60), (85, 100)];
+        let cubic = |t: f64| interpolate(t, &curve, Interpolation::MonotoneCubic);
+
+        // Passes through every point
+        for &(t, speed) in &curve {
+            assert!((cubic(t as f64) - speed as f64).abs() < 1e-9);
+        }
+        // Never decreasing, never outside the neighbouring points
+        let mut previous = 0.0;
+        for tenth in 300..=850 {
+            let speed = cubic(tenth as f64 / 10.0);
+            assert!(speed >= previous - 1e-9 && (0.0..=100.0).contains(&speed));
+            previous = speed;
+        }
+        // Smooth, so it differs from the straight lines between points
+        assert!((cubic(60.0) - interpolate(60.0, &curve, Interpolation::Linear)).abs() > 0.1);
+
+        // A plateau stays flat instead of bulging
+        let plateau = vec![(30, 20), (50, 50), (70, 50), (85, 100)];
+        for t in 50..=70 {
+            assert_eq!(calculate_target_speed(t, &plateau, Interpolation::MonotoneCubic), 50);
+        }
+    }
+
+    #[test]
+    fn test_offset() {
+        assert_eq!(apply_offset(50, 10), 60);
+        assert_eq!(apply_offset(50, -10), 40);
+        assert_eq!(apply_offset(5, -10), 0);
+        assert_eq!(apply_offset(95, 10), 100);
+    }
+
+    #[test]
+    fn test_hysteresis() {
+        let curve = vec![(30, 0), (50, 40), (80, 100)];
+        let mut previous = None;
+        let mut speed_at = |temp| {
+            let effective = hysteresis_temp(temp, previous, 3);
+            previous = Some(effective);
+            calculate_target_speed(effective, &curve, Interpolation::Linear)
+        };
+
+        assert_eq!(speed_at(60), 60);
+        // Hovering just below the temperature that raised the speed changes nothing
+        assert_eq!(speed_at(59), 60);
+        assert_eq!(speed_at(57), 60);
+        assert_eq!(speed_at(59), 60);
+        // Past the band the speed drops, trailing the temperature by 3°C
+        assert_eq!(speed_at(55), 56);
+        assert_eq!(speed_at(50), 46);
+        // Rising again is followed immediately
+        assert_eq!(speed_at(52), 46);
+        assert_eq!(speed_at(54), 48);
+
+        // Zero hysteresis follows the temperature exactly
+        assert_eq!(hysteresis_temp(50, Some(60), 0), 50);
+    }
+
+    #[test]
+    fn test_deadband() {
+        assert_eq!(apply_deadband(41, None, 3), 41);
+        assert_eq!(apply_deadband(41, Some(40), 3), 40);
+        assert_eq!(apply_deadband(38, Some(40), 3), 40);
+        assert_eq!(apply_deadband(43, Some(40), 3), 43);
+        // Endpoints always get through
+        assert_eq!(apply_deadband(100, Some(99), 3), 100);
+        assert_eq!(apply_deadband(0, Some(2), 3), 0);
+        assert_eq!(apply_deadband(41, Some(40), 0), 41);
+    }
+
+    #[test]
+    fn test_ema_smoothing() {
+        let mut smoother = Smoother::default();
+        let filter = TempFilter::Ema { alpha: 0.5 };
+        let series: Vec<f64> = [40, 60, 60, 60, 40].iter().map(|&t| smoother.update(t, &filter)).collect();
+        assert_eq!(series, vec![40.0, 50.0, 55.0, 57.5, 48.75]);
+    }
+
+    #[test]
+    fn test_moving_average() {
+        let mut smoother = Smoother::default();
+        let filter = TempFilter::MovingAverage { samples: 3 };
+        // A one-sample spike is spread over the window instead of hitting the curve
+        let series: Vec<f64> = [40, 40, 70, 40, 40, 40].iter().map(|&t| smoother.update(t, &filter)).collect();
+        assert_eq!(series, vec![40.0, 40.0, 50.0, 50.0, 50.0, 40.0]);
+
+        let mut raw = Smoother::default();
+        assert_eq!(raw.update(70, &TempFilter::None), 70.0);
+    }
+
+    #[test]
+    fn test_asymmetric_ramp() {
+        let rates = RampConfig { up: Some(20.0), down: Some(2.0) };
+        let mut ramp = Ramp::default();
+        // 2 second ticks: load hits, then goes away
+        let targets = [30.0, 80.0, 80.0, 80.0, 30.0, 30.0, 30.0];
+        let series: Vec<f64> = targets.iter().map(|&t| ramp.step(t, &rates, 2.0)).collect();
+        assert_eq!(series, vec![30.0, 70.0, 80.0, 80.0, 76.0, 72.0, 68.0]);
+
+        // Unlimited directions jump straight to the target
+        let mut ramp = Ramp::default();
+        let up_only = RampConfig { up: Some(5.0), down: None };
+        assert_eq!(ramp.step(80.0, &up_only, 1.0), 80.0);
+        assert_eq!(ramp.step(20.0, &up_only, 1.0), 20.0);
+        assert_eq!(ramp.step(40.0, &up_only, 1.0), 25.0);
+
+        ramp.set(Some(100.0));
+        assert_eq!(ramp.step(20.0, &rates, 1.0), 98.0);
+    }
+
+    #[test]
+    fn test_pid_holds_target() {
+        use crate::config::SimulationConfig;
+        use crate::hardware::backend::GpuBackend;
+        use crate::hardware::simulated::Simulated;
+
+        // Full load on a virtual card that would pass 90°C with the fans at 20%
+        let sim = Simulated::manual(SimulationConfig { load_profile: vec![(60, 100)], ..SimulationConfig::default() });
+        let config = PidConfig::default();
+        let mut pid = Pid::default();
+        let mut temps = Vec::new();
+        for _ in 0..600 {
+            let temp = sim.get_temp(0).unwrap();
+            let speed = pid.update(temp as f64, &config, 2.0).round() as u32;
+            assert!((config.min_speed..=config.max_speed).contains(&speed));
+            for fan in 0..2 {
+                sim.set_fan_speed(0, fan, speed).unwrap();
+            }
+            sim.advance(2.0);
+            temps.push(temp);
+        }
+
+        // Settled within a degree of the target for the last 10 minutes, after little overshoot
+        for &temp in &temps[temps.len() - 300..] {
+            assert!(temp.abs_diff(config.target_temp) <= 1, "settled at {}°C", temp);
+        }
+        assert!(temps.iter().all(|&t| t <= config.target_temp + 5));
+    }
+
+    #[test]
+    fn test_pid_anti_windup() {
+        let config = PidConfig { kd: 0.0, ..PidConfig::default() };
+        let mut pid = Pid::default();
+
+        // A long stretch far above target pins the output at the maximum...
+        for _ in 0..1000 {
+            assert_eq!(pid.update(90.0, &config, 2.0), 100.0);
+        }
+        // ...but the integral didn't grow without bound, so dropping below target
+        // releases the fans right away instead of hours later
+        assert!(pid.update(66.0, &config, 2.0) < 100.0);
+
+        // Below target the output rests at the minimum
+        let mut pid = Pid::default();
+        for _ in 0..100 {
+            assert_eq!(pid.update(40.0, &config, 2.0), 20.0);
+        }
+    }
+
+    #[test]
+    fn test_feed_forward_trace() {
+        let config = FeedForwardConfig { slope_gain: 10.0, usage_gain: 0.5, usage_jump: 20, decay_secs: 10.0, max_boost: 40 };
+        let mut ff = FeedForward::default();
+
+        // (temperature, utilization) every 2 seconds: idle, a render job starts, the card heats up
+        let trace = [(40.0, 5), (40.0, 5), (40.0, 95), (43.0, 95), (45.0, 95), (46.0, 95), (46.0, 95), (46.0, 95)];
+        let boosts: Vec<f64> = trace.iter().map(|&(t, u)| ff.update(t, Some(u), &config, 2.0)).collect();
+
+        assert_eq!(boosts[..2], [0.0, 0.0]);
+        // The load jump boosts before the temperature has moved at all
+        assert_eq!(boosts[2], 40.0);
+        // The slope keeps some boost up while heating, then it decays back towards the curve
+        let decay = (-0.2f64).exp();
+        assert!((boosts[3] - 40.0 * decay).abs() < 1e-9);
+        for pair in boosts[3..].windows(2) {
+            assert!(pair[1] < pair[0]);
+        }
+        assert!(boosts[7] < 20.0);
+
+        // Load dropping or a lost usage reading never boosts
+        assert_eq!(ff.update(46.0, Some(5), &config, 2.0), boosts[7] * decay);
+        let before = ff.update(46.0, None, &config, 2.0);
+        assert!(ff.update(46.0, Some(95), &config, 2.0) > before);
+    }
+
+    #[test]
+    fn test_feed_forward_keeps_card_cooler() {
+        use crate::config::SimulationConfig;
+        use crate::hardware::backend::GpuBackend;
+        use crate::hardware::simulated::Simulated;
+
+        // Minute-long render jobs with idle gaps, on two identical virtual cards
+        let run = |feed_forward: Option<FeedForwardConfig>| {
+            let sim = Simulated::manual(SimulationConfig { load_profile: vec![(60, 0), (60, 100)], ..SimulationConfig::default() });
+            let curve = [(40, 20), (60, 40), (80, 100)];
+            let mut ff = FeedForward::default();
+            let mut peak = 0;
+            // Degree-ticks above 60°C
+            let mut hot = 0;
+            let gpu = sim.detect_gpus().unwrap().remove(0);
+            for _ in 0..150 {
+                let temp = sim.get_temp(0).unwrap();
+                let usage = sim.get_gpu_usage(&gpu).unwrap();
+                let mut speed = interpolate(temp as f64, &curve, Interpolation::Linear);
+                if let Some(config) = &feed_forward {
+                    speed += ff.update(temp as f64, Some(usage), config, 2.0);
+                }
+                for fan in 0..2 {
+                    sim.set_fan_speed(0, fan, speed.round().min(100.0) as u32).unwrap();
+                }
+                sim.advance(2.0);
+                peak = peak.max(temp);
+                hot += temp.saturating_sub(60);
+            }
+            (peak, hot)
+        };
+
+        // Less time spent hot, and never a higher peak
+        let (with, without) = (run(Some(FeedForwardConfig::default())), run(None));
+        assert!(with.0 <= without.0);
+        assert!((with.1 as f64) < without.1 as f64 * 0.8, "{:?} vs {:?}", with, without);
+    }
+
+    #[test]
+    fn test_zero_rpm() {
+        let config = ZeroRpmConfig { stop_temp: 45, start_temp: 55, min_on_secs: 30, kick_speed: 60, kick_secs: 4 };
+        let mut zero_rpm = ZeroRpm::default();
+        let t0 = Instant::now();
+        let at = |secs: u64| t0 + Duration::from_secs(secs);
+
+        // (seconds, temperature) -> expected action, 2 second ticks
+        let series = [
+            (0, 50, ZeroRpmAction::Stop),
+            (2, 54, ZeroRpmAction::Stop),
+            (4, 55, ZeroRpmAction::Kick(60)),
+            (6, 55, ZeroRpmAction::Kick(60)),
+            (8, 50, ZeroRpmAction::Curve),
+            // Cool enough to stop, but the fans haven't run for min_on_secs yet
+            (10, 44, ZeroRpmAction::Curve),
+            (32, 44, ZeroRpmAction::Curve),
+            (34, 44, ZeroRpmAction::Stop),
+            // Between the thresholds they stay off
+            (36, 50, ZeroRpmAction::Stop),
+            (38, 56, ZeroRpmAction::Kick(60)),
+        ];
+        for (secs, temp, expected) in series {
+            assert_eq!(zero_rpm.update(temp, at(secs), &config), expected, "at {}s, {}°C", secs, temp);
+        }
+
+        // Starting hot spins the fans up right away
+        let mut hot = ZeroRpm::default();
+        assert_eq!(hot.update(70, t0, &config), ZeroRpmAction::Kick(60));
     }
 }
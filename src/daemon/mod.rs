@@ -1,4 +1,10 @@
+pub mod calibrate;
+pub mod critical;
+pub mod formula;
 pub mod math;
+pub mod signals;
+pub mod stall;
+pub mod supervisor;
 pub mod r#loop; // "loop" is a keyword in Rust, so we use raw identifier or rename file. 
                // Actually, usually we name the file "control_loop.rs" or similar to avoid this.
                // Let's stick to "loop.rs" file but module name usage might need "r#loop".
@@ -0,0 +1,67 @@
+//! Shutdown signal handling for daemon mode
+//! The signals are blocked in every thread and collected synchronously with sigtimedwait,
+//! so no async-signal-safety rules apply to what we do afterwards.
+
+use std::io;
+use std::mem::MaybeUninit;
+use std::time::Duration;
+use anyhow::Result;
+
+const SHUTDOWN_SIGNALS: [libc::c_int; 3] = [libc::SIGINT, libc::SIGTERM, libc::SIGHUP];
+
+pub struct ShutdownSignals {
+    set: libc::sigset_t,
+}
+
+impl ShutdownSignals {
+    /// Block SIGINT, SIGTERM and SIGHUP for this thread and every thread spawned after it.
+    /// Call before starting the daemon so the worker thread inherits the mask.
+    pub fn block() -> Result<Self> {
+        // SAFETY: sigemptyset initializes the set before anything reads it
+        let set = unsafe {
+            let mut set = MaybeUninit::<libc::sigset_t>::uninit();
+            libc::sigemptyset(set.as_mut_ptr());
+            let mut set = set.assume_init();
+            for sig in SHUTDOWN_SIGNALS {
+                libc::sigaddset(&mut set, sig);
+            }
+            set
+        };
+
+        // SAFETY: set is initialized, the old mask is not needed
+        let ret = unsafe { libc::pthread_sigmask(libc::SIG_BLOCK, &set, std::ptr::null_mut()) };
+        if ret != 0 {
+            anyhow::bail!("pthread_sigmask failed: {}", io::Error::from_raw_os_error(ret));
+        }
+        Ok(Self { set })
+    }
+
+    /// Sleep until one of the signals arrives and return it, or None after `timeout`
+    pub fn wait_timeout(&self, timeout: Duration) -> Result<Option<libc::c_int>> {
+        let ts = libc::timespec {
+            tv_sec: timeout.as_secs() as libc::time_t,
+            tv_nsec: timeout.subsec_nanos() as libc::c_long,
+        };
+        // SAFETY: set and ts are valid for the duration of the call, siginfo is optional
+        let sig = unsafe { libc::sigtimedwait(&self.set, std::ptr::null_mut(), &ts) };
+        if sig >= 0 {
+            return Ok(Some(sig));
+        }
+
+        let err = io::Error::last_os_error();
+        match err.raw_os_error() {
+            Some(libc::EAGAIN) | Some(libc::EINTR) => Ok(None),
+            _ => anyhow::bail!("sigtimedwait failed: {}", err),
+        }
+    }
+}
+
+/// Signal name for logs
+pub fn name(sig: libc::c_int) -> &'static str {
+    match sig {
+        libc::SIGINT => "SIGINT",
+        libc::SIGTERM => "SIGTERM",
+        libc::SIGHUP => "SIGHUP",
+        _ => "signal",
+    }
+}
@@ -0,0 +1,151 @@
+//! Fan stall detection
+//! A fan that reads 0 RPM, or far fewer RPM than its duty cycle should give, for longer
+//! than the grace period is marked as failed until it spins properly again.
+
+use std::time::{Duration, Instant};
+
+use crate::config::StallConfig;
+
+/// What changed for a fan on this reading
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StallEvent {
+    Failed,
+    Recovered,
+}
+
+#[derive(Debug, Clone, Default)]
+struct FanState {
+    /// Since when the fan has been reading too slow
+    slow_since: Option<Instant>,
+    failed: bool,
+    /// Duty of the last reading, and since when it has been unchanged
+    duty_since: Option<(u32, Instant)>,
+    /// Lowest RPM per percent of duty seen at a settled duty while healthy,
+    /// what the fan can be relied on to do
+    rpm_per_percent: Option<f64>,
+}
+
+/// Stall state of every fan on one GPU
+#[derive(Debug, Default)]
+pub struct StallDetector {
+    fans: Vec<FanState>,
+}
+
+impl StallDetector {
+    /// Feed a fan's duty cycle and RPM reading; returns a change of its failure state.
+    /// `calibrated` is the RPM a calibrated fan reaches at this duty, used instead of what was learned.
+    pub fn update(
+        &mut self,
+        index: usize,
+        duty: u32,
+        rpm: Option<u32>,
+        calibrated: Option<u32>,
+        now: Instant,
+        config: &StallConfig,
+    ) -> Option<StallEvent> {
+        if self.fans.len() <= index {
+            self.fans.resize(index + 1, FanState::default());
+        }
+        let fan = &mut self.fans[index];
+
+        // Right after a duty change the fan still spins at the old speed
+        let since = match fan.duty_since {
+            Some((last, since)) if last == duty => since,
+            _ => {
+                fan.duty_since = Some((duty, now));
+                now
+            }
+        };
+        let settled = since < now && now.duration_since(since) >= Duration::from_secs(config.grace_secs);
+
+        // Low duties may legitimately not turn the fan at all
+        let Some(rpm) = rpm.filter(|_| duty >= config.min_duty.max(1)) else {
+            fan.slow_since = None;
+            return None;
+        };
+
+        let expected = calibrated.map(f64::from).or(fan.rpm_per_percent.map(|ratio| ratio * duty as f64));
+        let slow = rpm == 0 || expected.is_some_and(|expected| (rpm as f64) < expected * config.min_ratio);
+        if !slow {
+            if settled {
+                let ratio = rpm as f64 / duty as f64;
+                fan.rpm_per_percent = Some(fan.rpm_per_percent.map_or(ratio, |known| known.min(ratio)));
+            }
+            fan.slow_since = None;
+            return std::mem::take(&mut fan.failed).then_some(StallEvent::Recovered);
+        }
+
+        let since = *fan.slow_since.get_or_insert(now);
+        if !fan.failed && now.duration_since(since) >= Duration::from_secs(config.grace_secs) {
+            fan.failed = true;
+            return Some(StallEvent::Failed);
+        }
+        None
+    }
+
+    pub fn failed(&self, index: usize) -> bool {
+        self.fans.get(index).is_some_and(|fan| fan.failed)
+    }
+
+    pub fn any_failed(&self) -> bool {
+        self.fans.iter().any(|fan| fan.failed)
+    }
+
+    /// Forget pending slow readings, e.g. while the driver runs the fans.
+    /// Failures and what each fan is capable of are kept.
+    pub fn pause(&mut self) {
+        for fan in &mut self.fans {
+            fan.slow_since = None;
+            fan.duty_since = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stall_detection() {
+        let config = StallConfig { grace_secs: 10, ..StallConfig::default() };
+        let mut detector = StallDetector::default();
+        let start = Instant::now();
+        let at = |secs: u64| start + Duration::from_secs(secs);
+
+        // Healthy: 60% gives 1800 RPM, which teaches the detector what the fan can do
+        // once it has held that duty for the grace period
+        assert_eq!(detector.update(0, 60, Some(1800), None, at(0), &config), None);
+        assert_eq!(detector.update(0, 60, Some(1800), None, at(11), &config), None);
+
+        // Just after a drop to 30% the fan still turns 3000 RPM; learning 100 RPM/% from
+        // that would make a healthy fan at 100% look far too slow
+        assert_eq!(detector.update(0, 30, Some(3000), None, at(12), &config), None);
+        assert_eq!(detector.update(0, 100, Some(2900), None, at(13), &config), None);
+        assert_eq!(detector.update(0, 100, Some(2900), None, at(24), &config), None);
+        assert!(!detector.failed(0));
+
+        // Stopped with a duty too low to matter, or no reading at all
+        assert_eq!(detector.update(0, 20, Some(0), None, at(25), &config), None);
+        assert_eq!(detector.update(0, 60, None, None, at(26), &config), None);
+
+        // Far below what 60% should give, but only a failure once the grace period is over
+        assert_eq!(detector.update(0, 60, Some(400), None, at(27), &config), None);
+        assert_eq!(detector.update(0, 60, Some(400), None, at(36), &config), None);
+        assert_eq!(detector.update(0, 60, Some(400), None, at(37), &config), Some(StallEvent::Failed));
+        assert!(detector.failed(0) && detector.any_failed());
+        assert_eq!(detector.update(0, 100, Some(0), None, at(38), &config), None);
+
+        // Spinning properly again
+        assert_eq!(detector.update(0, 100, Some(2900), None, at(39), &config), Some(StallEvent::Recovered));
+        assert!(!detector.any_failed());
+
+        // A fan never seen healthy is still caught at 0 RPM
+        assert_eq!(detector.update(1, 50, Some(0), None, at(40), &config), None);
+        assert_eq!(detector.update(1, 50, Some(0), None, at(50), &config), Some(StallEvent::Failed));
+        assert!(detector.failed(1) && !detector.failed(0));
+
+        // A calibrated fan is held to its measured speed from the first reading
+        assert_eq!(detector.update(2, 60, Some(500), Some(1800), at(60), &config), None);
+        assert_eq!(detector.update(2, 60, Some(500), Some(1800), at(70), &config), Some(StallEvent::Failed));
+    }
+}
@@ -0,0 +1,186 @@
+//! Watchdog for daemon mode
+//! `--daemon` runs the control loop in a child process and waits on it. If the child dies
+//! without handing the fans back (a crash, an abort, the OOM killer), the supervisor does it,
+//! so a card is never left stuck at a fixed speed.
+
+use std::ffi::OsStr;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::process::{Child, Command};
+use std::time::Duration;
+use anyhow::{Context, Result};
+use log::{error, info, warn};
+
+use crate::config::Config;
+use crate::hardware::{self, backend::GpuBackend};
+use super::signals::{self, ShutdownSignals};
+
+/// Marks the child process started by the supervisor
+pub const SUPERVISED_FLAG: &str = "--supervised";
+
+/// How often the supervisor checks on its child
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Run the daemon under supervision and return the process exit code
+pub fn run(simulate: bool) -> i32 {
+    // Blocked here so the signals reach us through sigtimedwait. Spawning resets the mask,
+    // so the child gets them delivered normally until it blocks them itself
+    let shutdown = match ShutdownSignals::block() {
+        Ok(shutdown) => shutdown,
+        Err(e) => {
+            error!("Failed to set up signal handling: {}", e);
+            return 1;
+        }
+    };
+
+    let mut child = match spawn_child(simulate) {
+        Ok(child) => child,
+        Err(e) => {
+            error!("Failed to start the control process: {:#}", e);
+            return 1;
+        }
+    };
+    info!("Supervising control process {}", child.id());
+
+    loop {
+        match shutdown.wait_timeout(POLL_INTERVAL) {
+            Ok(Some(sig)) => {
+                info!("Received {}, stopping control process", signals::name(sig));
+                forward(&child, sig);
+            }
+            Ok(None) => {}
+            Err(e) => {
+                error!("{}, stopping control process", e);
+                forward(&child, libc::SIGTERM);
+            }
+        }
+
+        match child.try_wait() {
+            Ok(Some(status)) if status.success() => {
+                info!("Control process exited cleanly");
+                return 0;
+            }
+            Ok(Some(status)) => {
+                error!("Control process died ({}), handing the fans back to the driver", status);
+                restore_fans(&*hardware::select_backend(&load_config(), simulate));
+                return 1;
+            }
+            Ok(None) => {}
+            Err(e) => warn!("Could not check on control process: {}", e),
+        }
+    }
+}
+
+fn spawn_child(simulate: bool) -> Result<Child> {
+    let exe = std::env::current_exe().context("locating own executable")?;
+    let mut cmd = Command::new(exe);
+    cmd.args(["--daemon", SUPERVISED_FLAG]);
+    if simulate {
+        cmd.arg("--simulate");
+    }
+    Ok(cmd.spawn()?)
+}
+
+fn forward(child: &Child, sig: libc::c_int) {
+    // SAFETY: plain syscall on a pid we own and have not reaped yet
+    if unsafe { libc::kill(child.id() as libc::pid_t, sig) } != 0 {
+        warn!("Could not signal control process: {}", std::io::Error::last_os_error());
+    }
+}
+
+/// Make the control process exit if the supervisor goes away first
+pub fn exit_with_parent() {
+    // SAFETY: prctl with PR_SET_PDEATHSIG only takes integer arguments
+    if unsafe { libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGTERM) } != 0 {
+        warn!("Could not tie the control process to its supervisor: {}", std::io::Error::last_os_error());
+    }
+}
+
+/// Pids of other nvidia-wormhole processes, daemon or app, which would fight over the fans
+pub fn other_instances() -> Vec<u32> {
+    let Some(name) = std::env::current_exe().ok().and_then(|exe| exe.file_name().map(OsStr::to_owned)) else {
+        return Vec::new();
+    };
+    let Ok(entries) = std::fs::read_dir("/proc") else {
+        return Vec::new();
+    };
+    let own = std::process::id();
+    entries
+        .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse::<u32>().ok())
+        .filter(|&pid| pid != own)
+        .filter(|pid| std::fs::read(format!("/proc/{}/cmdline", pid)).is_ok_and(|cmdline| runs(&cmdline, &name)))
+        .collect()
+}
+
+/// Whether a NUL-separated command line starts with a program of this file name
+fn runs(cmdline: &[u8], name: &OsStr) -> bool {
+    let program = cmdline.split(|&b| b == 0).next().unwrap_or_default();
+    Path::new(OsStr::from_bytes(program)).file_name() == Some(name)
+}
+
+pub fn load_config() -> Config {
+    Config::load().unwrap_or_else(|e| {
+        warn!("Failed to load config: {}, using defaults", e);
+        Config::default()
+    })
+}
+
+/// Hand every fan on every GPU back to the driver and lift any power cap the
+/// critical ladder left behind. Returns false if any GPU failed.
+pub fn restore_fans(backend: &dyn GpuBackend) -> bool {
+    let gpus = match backend.detect_gpus() {
+        Ok(gpus) => gpus,
+        Err(e) => {
+            error!("Could not detect GPUs to restore: {}", e);
+            return false;
+        }
+    };
+
+    let mut ok = true;
+    for gpu in &gpus {
+        match backend.restore_auto(gpu) {
+            Ok(()) => info!("GPU {}: fans returned to driver control", gpu.id),
+            Err(e) => {
+                error!("GPU {}: failed to restore driver fan control: {}", gpu.id, e);
+                ok = false;
+            }
+        }
+        if let Err(e) = backend.set_power_limit(gpu, None) {
+            error!("GPU {}: failed to restore the default power limit: {:#}", gpu.id, e);
+            ok = false;
+        }
+    }
+    ok
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::SimulationConfig;
+    use crate::hardware::simulated::Simulated;
+
+    #[test]
+    fn test_restore_fans() {
+        let sim = Simulated::manual(SimulationConfig { gpus: 2, load_profile: vec![(60, 100)], ..SimulationConfig::default() });
+        let gpu = sim.detect_gpus().unwrap().remove(1);
+        let full_power = sim.get_power_draw(&gpu).unwrap();
+        sim.set_fan_speed(1, 0, 0).unwrap();
+        sim.set_power_limit(&gpu, Some(full_power / 2)).unwrap();
+        assert!(sim.manual_control(1));
+        assert!(sim.get_power_draw(&gpu).unwrap() < full_power);
+
+        assert!(restore_fans(&sim));
+        assert!(!sim.manual_control(0) && !sim.manual_control(1));
+        assert_eq!(sim.get_power_draw(&gpu).unwrap(), full_power);
+    }
+
+    #[test]
+    fn test_finds_other_instances() {
+        let name = OsStr::new("nvidia-wormhole");
+        assert!(runs(b"/usr/bin/nvidia-wormhole\0--daemon\0--supervised\0", name));
+        assert!(runs(b"nvidia-wormhole\0", name));
+        assert!(!runs(b"/usr/bin/nvidia-wormhole-helper\0", name));
+        assert!(!runs(b"vim\0nvidia-wormhole\0", name));
+        assert!(!runs(b"", name));
+    }
+}
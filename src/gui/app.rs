@@ -1,23 +1,33 @@
 use iced::{
-    widget::{button, column, container, row, slider, text, Space, checkbox},
+    widget::{button, canvas, column, container, row, slider, text, Space, checkbox},
     window, Alignment, Element, Length, Settings, Theme, Subscription, Application, Command,
     time::Duration,
 };
-use crate::config::Config;
-use crate::daemon::r#loop::DaemonState;
+use std::sync::{atomic::{AtomicBool, Ordering}, Arc};
+use std::thread::JoinHandle;
+use crate::config::{self, Config, ControlMode, CurveInput, FanCalibration, Interpolation};
+use crate::daemon::calibrate;
+use crate::daemon::r#loop::{DaemonState, GpuStatus};
+use crate::hardware::backend::GpuBackend;
+use crate::hardware::probe::Gpu;
 use crate::setup;
+use crate::gui::curve_view::CurveView;
 use crate::gui::style;
 
-pub fn run() -> iced::Result {
+const NO_GPU_KEY: &str = "✗ This GPU has no UUID or PCI bus ID to store fan settings under";
+
+pub fn run(simulate: bool) -> iced::Result {
     let icon = load_icon();
     
     NvidiaWormhole::run(Settings {
+        flags: Flags { simulate },
         default_font: iced::Font::DEFAULT,
         window: window::Settings {
             icon,
+            // Closing goes through Message::CloseRequested so the fans get released first
+            exit_on_close_request: false,
             platform_specific: window::settings::PlatformSpecific {
                 application_id: "com.github.doublepranks.nvidia-wormhole".into(),
-                ..Default::default()
             },
             ..Default::default()
         },
@@ -40,10 +50,32 @@ pub enum Message {
     Tick,
     InstallPermissions,
     ToggleAutostart(bool),
+    SelectGpu(usize),
+    SelectCurve(CurveTarget),
+    ToggleFanCurve(bool),
+    FanOffsetChanged(f64),
+    SelectInterpolation(Interpolation),
+    Calibrate,
+    CalibrationDone(Result<Vec<(Gpu, Vec<FanCalibration>)>, String>),
+    CloseRequested(window::Id),
+    /// The fans are back with the driver and the window can go
+    Released(window::Id),
+}
+
+/// Which curve the editor is showing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CurveTarget {
+    /// The curve every fan follows by default
+    Shared,
+    /// Overrides for one fan of the selected GPU, by position
+    Fan(usize),
 }
 
 #[derive(Default)]
-pub struct Flags;
+pub struct Flags {
+    /// Drive the dashboard from the virtual GPU instead of real hardware
+    pub simulate: bool,
+}
 
 pub struct NvidiaWormhole {
     daemon_state: DaemonState,
@@ -53,13 +85,32 @@ pub struct NvidiaWormhole {
     current_temp: u32,
     current_speed: u32,
     current_usage: u32,
-    gpu_name: String,
-    fan_speed_points: [f64; 4],
+    fan_speeds: Vec<u32>,
+    actual_speeds: Vec<Option<u32>>,
+    fan_rpms: Vec<Option<u32>>,
+    failed_fans: Vec<bool>,
+    fail_safe: Option<String>,
+    critical: Option<u32>,
+    pid_target: Option<u32>,
+    driver_until: Option<u32>,
+    gpus: Vec<Gpu>,
+    /// Fan speeds each GPU accepts, in the order of `gpus`
+    fan_ranges: Vec<(u32, u32)>,
+    selected_gpu: usize,
+    curve_target: CurveTarget,
+    fan_speed_points: Vec<f64>,
     
     // Setup State
+    simulate: bool,
     has_permissions: bool,
     autostart_enabled: bool,
     setup_message: Option<String>,
+    /// A calibration sweep has the fans, the daemon is stopped meanwhile
+    calibrating: bool,
+    /// Set to stop the sweep early, with its worker thread
+    calibration: Option<(Arc<AtomicBool>, JoinHandle<()>)>,
+    /// The window is waiting for the fans to be released before it closes
+    closing: bool,
 }
 
 impl Application for NvidiaWormhole {
@@ -68,22 +119,29 @@ impl Application for NvidiaWormhole {
     type Executor = iced::executor::Default;
     type Flags = Flags;
 
-    fn new(_flags: Flags) -> (Self, Command<Message>) {
-        let has_permissions = setup::check_permissions();
+    fn new(flags: Flags) -> (Self, Command<Message>) {
+        // The virtual GPU needs no sudo access
+        let has_permissions = flags.simulate || setup::check_permissions();
         let autostart_enabled = setup::is_autostart_enabled();
         
-        // Get GPU Name
-        let gpu_name = crate::hardware::probe::get_gpu_name(0).unwrap_or_else(|_| "Nvidia GPU".to_string());
-        
         let config = Config::load().unwrap_or_else(|e| {
             log::warn!("Failed to load config: {}, using defaults", e);
             Config::default()
         });
         
-        let fan_speed_points = config.curve_speeds_f64();
+        let backend = crate::hardware::select_backend(&config, flags.simulate);
+        
+        // Get GPUs
+        let gpus: Vec<Gpu> = match backend.detect_gpus() {
+            Ok(gpus) if !gpus.is_empty() => gpus,
+            _ => vec![Gpu { id: 0, name: "Nvidia GPU".to_string(), fans: vec![0], ..Default::default() }],
+        };
+        
+        let fan_ranges = fan_ranges(&*backend, &gpus);
+        let fan_speed_points = config::curve_speeds_f64(config.curve_for(&gpus[0]));
         let interval_ms = config.interval_ms;
         
-        let daemon_state = DaemonState::new(config.curve.clone());
+        let daemon_state = DaemonState::new(config.clone(), backend);
         // Only start daemon if permissions are already granted
         if has_permissions {
             daemon_state.start(interval_ms);
@@ -96,18 +154,37 @@ impl Application for NvidiaWormhole {
                 current_temp: 0,
                 current_speed: 0,
                 current_usage: 0,
-                gpu_name,
+                fan_speeds: Vec::new(),
+                actual_speeds: Vec::new(),
+                fan_rpms: Vec::new(),
+                failed_fans: Vec::new(),
+                fail_safe: None,
+                critical: None,
+                pid_target: None,
+                driver_until: None,
+                gpus,
+                fan_ranges,
+                selected_gpu: 0,
+                curve_target: CurveTarget::Shared,
                 fan_speed_points,
+                simulate: flags.simulate,
                 has_permissions,
                 autostart_enabled,
                 setup_message: None,
+                calibrating: false,
+                calibration: None,
+                closing: false,
             },
             Command::none(),
         )
     }
 
     fn title(&self) -> String {
-        String::from("Nvidia Wormhole")
+        if self.simulate {
+            String::from("Nvidia Wormhole (simulated)")
+        } else {
+            String::from("Nvidia Wormhole")
+        }
     }
 
     fn theme(&self) -> Theme {
@@ -119,33 +196,101 @@ impl Application for NvidiaWormhole {
             Message::FanPointChanged(idx, val) => {
                 if idx < self.fan_speed_points.len() {
                     self.fan_speed_points[idx] = val;
-                    self.config.set_curve_speeds(&self.fan_speed_points);
-                    if let Ok(mut curve) = self.daemon_state.curve.lock() {
-                        *curve = self.config.curve.clone();
+                    match self.curve_target {
+                        CurveTarget::Shared => {
+                            let gpu = self.gpus[self.selected_gpu].clone();
+                            let curve = config::curve_with_speeds(self.config.curve_for(&gpu), &self.fan_speed_points);
+                            self.config.set_curve_for(&gpu, curve);
+                        }
+                        CurveTarget::Fan(fan) => {
+                            let gpu = self.gpus[self.selected_gpu].clone();
+                            let curve = config::curve_with_speeds(self.config.fan_curve_for(&gpu, fan), &self.fan_speed_points);
+                            if let Some(entry) = self.config.fan_config_mut(&gpu, fan) {
+                                entry.curve = Some(curve);
+                            }
+                        }
+                    }
+                    self.apply_config();
+                }
+            }
+            Message::SelectCurve(target) => {
+                self.curve_target = target;
+                self.load_curve_points();
+            }
+            Message::ToggleFanCurve(enabled) => {
+                if let CurveTarget::Fan(fan) = self.curve_target {
+                    let gpu = self.gpus[self.selected_gpu].clone();
+                    // Start the fan's own curve from whatever it follows today
+                    let current = self.config.fan_curve_for(&gpu, fan).to_vec();
+                    let interpolation = self.config.fan_interpolation_for(&gpu, fan);
+                    match self.config.fan_config_mut(&gpu, fan) {
+                        Some(entry) => {
+                            entry.curve = if enabled { Some(current) } else { None };
+                            entry.interpolation = if enabled { Some(interpolation) } else { None };
+                            self.config.prune();
+                            self.load_curve_points();
+                            self.apply_config();
+                        }
+                        None => self.setup_message = Some(NO_GPU_KEY.into()),
+                    }
+                }
+            }
+            Message::FanOffsetChanged(val) => {
+                if let CurveTarget::Fan(fan) = self.curve_target {
+                    let gpu = self.gpus[self.selected_gpu].clone();
+                    match self.config.fan_config_mut(&gpu, fan) {
+                        Some(entry) => {
+                            entry.offset = val as i32;
+                            self.config.prune();
+                            self.apply_config();
+                        }
+                        None => self.setup_message = Some(NO_GPU_KEY.into()),
+                    }
+                }
+            }
+            Message::SelectInterpolation(mode) => {
+                match self.curve_target {
+                    CurveTarget::Shared => {
+                        let gpu = self.gpus[self.selected_gpu].clone();
+                        self.config.set_interpolation_for(&gpu, mode);
                     }
-                    if let Err(e) = self.config.save() {
-                        log::error!("Failed to save config: {}", e);
+                    CurveTarget::Fan(fan) => {
+                        let gpu = self.gpus[self.selected_gpu].clone();
+                        if let Some(entry) = self.config.fan_config_mut(&gpu, fan) {
+                            entry.interpolation = Some(mode);
+                        }
                     }
                 }
+                self.apply_config();
             }
             Message::OpenLink(url) => {
                 let _ = open::that(url);
             }
             Message::Tick => {
-                if let Ok(status) = self.daemon_state.status.lock() {
-                    self.current_temp = status.current_temp;
-                    self.current_speed = status.current_speed;
-                    self.current_usage = status.gpu_usage;
+                let status = self.daemon_state.status.lock().ok().map(|s| s.gpus.clone());
+                if let Some(gpus) = status {
+                    // Prefer the daemon's view of the GPUs once it is running
+                    if !gpus.is_empty() {
+                        let changed = !self.gpus.iter().map(|g| g.id).eq(gpus.iter().map(|g| g.gpu.id));
+                        self.gpus = gpus.iter().map(|g| g.gpu.clone()).collect();
+                        if changed {
+                            self.fan_ranges = fan_ranges(&*self.daemon_state.backend, &self.gpus);
+                        }
+                        self.selected_gpu = self.selected_gpu.min(self.gpus.len() - 1);
+                    }
+                    if let Some(gpu) = gpus.get(self.selected_gpu) {
+                        self.show_status(gpu);
+                    }
                 }
-                self.has_permissions = setup::check_permissions();
+                self.has_permissions = self.simulate || setup::check_permissions();
             }
             Message::InstallPermissions => {
                 match setup::install_sudoers() {
                     Ok(_) => {
                         self.has_permissions = true;
                         self.setup_message = Some("✓ Permissions installed!".into());
-                        // Now that we have permissions, start the daemon
-                        if !self.daemon_state.running.load(std::sync::atomic::Ordering::Relaxed) {
+                        // Now that we have permissions, start the daemon, unless a sweep has the fans
+                        if !self.calibrating && !self.daemon_state.running.load(Ordering::Relaxed) {
                             self.daemon_state.start(self.config.interval_ms);
                         }
                     }
@@ -154,6 +299,92 @@ impl Application for NvidiaWormhole {
                     }
                 }
             }
+            Message::SelectGpu(idx) => {
+                if idx < self.gpus.len() {
+                    self.selected_gpu = idx;
+                    self.curve_target = CurveTarget::Shared;
+                    self.load_curve_points();
+                    let status = self.daemon_state.status.lock().ok().and_then(|s| s.gpus.get(idx).cloned());
+                    if let Some(gpu) = status {
+                        self.show_status(&gpu);
+                    }
+                }
+            }
+            Message::Calibrate => {
+                self.calibrating = true;
+                self.setup_message = Some("Calibrating fans, keep the GPU idle...".into());
+
+                let daemon_state = self.daemon_state.clone();
+                let cancel = Arc::new(AtomicBool::new(false));
+                let (sender, receiver) = iced::futures::channel::oneshot::channel();
+                let worker = {
+                    let cancel = cancel.clone();
+                    std::thread::spawn(move || {
+                        // The daemon would fight the sweep for the fans; stopping it waits for its last tick
+                        daemon_state.stop();
+                        let backend = &*daemon_state.backend;
+                        let result = calibrate::calibrate_all(backend, &cancel, &mut std::thread::sleep)
+                            .map_err(|e| format!("{:#}", e));
+                        let _ = sender.send(result);
+                    })
+                };
+                self.calibration = Some((cancel, worker));
+                return Command::perform(
+                    async move { receiver.await.unwrap_or_else(|_| Err("calibration stopped unexpectedly".into())) },
+                    Message::CalibrationDone,
+                );
+            }
+            Message::CalibrationDone(result) => {
+                self.calibrating = false;
+                self.calibration = None;
+                let stored = result.and_then(|results| {
+                    calibrate::store(&mut self.config, results).map_err(|e| format!("{:#}", e))
+                });
+                match stored {
+                    Ok(()) => {
+                        self.apply_config();
+                        self.setup_message = Some("✓ Fans calibrated".into());
+                    }
+                    Err(e) => self.setup_message = Some(format!("✗ Calibration failed: {}", e)),
+                }
+                // A sweep cancelled by closing the window must not bring the daemon back
+                if self.has_permissions && !self.closing {
+                    self.daemon_state.start(self.config.interval_ms);
+                }
+            }
+            Message::CloseRequested(id) => {
+                if self.closing {
+                    return Command::none();
+                }
+                self.closing = true;
+                self.setup_message = Some("Handing the fans back to the driver...".into());
+
+                // Hand the fans back to the driver before the window goes away, off the UI thread.
+                // A sweep does that itself once cancelled, within a second or so.
+                let calibration = self.calibration.take();
+                let daemon_state = self.daemon_state.clone();
+                let gpus = self.gpus.clone();
+                let (sender, receiver) = iced::futures::channel::oneshot::channel();
+                std::thread::spawn(move || {
+                    if let Some((cancel, worker)) = calibration {
+                        cancel.store(true, Ordering::Relaxed);
+                        if worker.join().is_err() {
+                            // It died before it could hand the fans back
+                            for gpu in &gpus {
+                                if let Err(e) = daemon_state.backend.restore_auto(gpu) {
+                                    log::error!("Failed to hand GPU {} fans back to the driver: {:#}", gpu.id, e);
+                                }
+                            }
+                        }
+                    }
+                    daemon_state.stop();
+                    let _ = sender.send(());
+                });
+                return Command::perform(async move { receiver.await.ok() }, move |_| Message::Released(id));
+            }
+            Message::Released(id) => {
+                return window::close(id);
+            }
             Message::ToggleAutostart(enabled) => {
                 let binary_path = std::env::current_exe()
                     .map(|p| p.to_string_lossy().to_string())
@@ -184,7 +415,13 @@ impl Application for NvidiaWormhole {
     }
 
     fn subscription(&self) -> Subscription<Message> {
-        iced::time::every(Duration::from_millis(1000)).map(|_| Message::Tick)
+        Subscription::batch([
+            iced::time::every(Duration::from_millis(1000)).map(|_| Message::Tick),
+            iced::event::listen_with(|event, _status| match event {
+                iced::Event::Window(id, window::Event::CloseRequested) => Some(Message::CloseRequested(id)),
+                _ => None,
+            }),
+        ])
     }
 
     fn view(&self) -> Element<'_, Message> {
@@ -209,12 +446,111 @@ impl Application for NvidiaWormhole {
             Space::with_height(0).into()
         };
 
-        // 2. Dashboard
+        let fail_safe_warning: Element<'_, Message> = match &self.fail_safe {
+            Some(reason) => container(
+                column![
+                    text("⚠ Sensor Fail-Safe Active").size(16),
+                    text(reason).size(12),
+                    text("The curve is suspended until the temperature readings make sense again").size(12).style(style::TEXT_DIM),
+                ]
+                .spacing(5)
+                .align_items(Alignment::Center)
+            )
+            .padding(15)
+            .style(style::warning_card)
+            .width(Length::Fill)
+            .into(),
+            None => Space::with_height(0).into(),
+        };
+
+        let critical_warning: Element<'_, Message> = match self.critical {
+            Some(threshold) => container(
+                column![
+                    text(format!("🔥 Critical Temperature: above {}°C", threshold)).size(16),
+                    text("Safety actions are overriding the fan curve").size(12).style(style::TEXT_DIM),
+                ]
+                .spacing(5)
+                .align_items(Alignment::Center)
+            )
+            .padding(15)
+            .style(style::warning_card)
+            .width(Length::Fill)
+            .into(),
+            None => Space::with_height(0).into(),
+        };
+
+        let failed: Vec<String> = self.failed_fans.iter()
+            .enumerate()
+            .filter(|(_, &failed)| failed)
+            .map(|(i, _)| format!("Fan {}", i))
+            .collect();
+        let fan_failure_warning: Element<'_, Message> = if failed.is_empty() {
+            Space::with_height(0).into()
+        } else {
+            container(
+                column![
+                    text(format!("⚠ Fan Failure: {}", failed.join(", "))).size(16),
+                    text("Not spinning as fast as commanded; the card's other fans are running at full speed").size(12).style(style::TEXT_DIM),
+                ]
+                .spacing(5)
+                .align_items(Alignment::Center)
+            )
+            .padding(15)
+            .style(style::warning_card)
+            .width(Length::Fill)
+            .into()
+        };
+
+        // 2. GPU Selector (only shown on multi-GPU systems)
+        let gpu_selector: Element<'_, Message> = if self.gpus.len() > 1 {
+            let mut tabs = row![].spacing(10);
+            for i in 0..self.gpus.len() {
+                let label = text(format!("GPU {}", i)).size(14);
+                let tab = if i == self.selected_gpu {
+                    button(label).padding([6, 14])
+                } else {
+                    button(label).on_press(Message::SelectGpu(i)).padding([6, 14])
+                };
+                tabs = tabs.push(tab);
+            }
+            tabs.into()
+        } else {
+            Space::with_height(0).into()
+        };
+
+        // 3. Dashboard
+        let fan_breakdown: Element<'_, Message> = if let Some(takeover) = self.driver_until {
+            text(format!("Driver in control until {}°C", takeover)).size(12).style(style::TEXT_DIM).into()
+        } else if self.fan_speeds.len() > 1 || !self.actual_speeds.is_empty() {
+            // Target, then what the fan reads back at where the card reports it
+            let speeds: Vec<String> = self.fan_speeds.iter()
+                .enumerate()
+                .map(|(i, speed)| {
+                    let actual = match (self.actual_speeds.get(i).copied().flatten(), self.fan_rpms.get(i).copied().flatten()) {
+                        (Some(actual), Some(rpm)) => format!(" (at {}%, {} RPM)", actual, rpm),
+                        (Some(actual), None) => format!(" (at {}%)", actual),
+                        (None, Some(rpm)) => format!(" ({} RPM)", rpm),
+                        (None, None) => String::new(),
+                    };
+                    format!("Fan {}: {}%{}", i, speed, actual)
+                })
+                .collect();
+            text(speeds.join(" · ")).size(12).style(style::TEXT_DIM).into()
+        } else {
+            Space::with_height(0).into()
+        };
+
+        let pid_target: Element<'_, Message> = match self.pid_target {
+            Some(target) => text(format!("PID target {}°C", target)).size(12).style(style::TEXT_DIM).into(),
+            None => Space::with_height(0).into(),
+        };
+
         let dashboard = row![
             container(
                 column![
                     text("GPU TEMP").size(12).style(style::TEXT_DIM),
-                    text(format!("{}°C", self.current_temp)).size(36).style(style::NVIDIA_GREEN)
+                    text(format!("{}°C", self.current_temp)).size(36).style(style::NVIDIA_GREEN),
+                    pid_target,
                 ].align_items(Alignment::Center)
             )
             .style(style::metric_card)
@@ -238,7 +574,11 @@ impl Application for NvidiaWormhole {
             container(
                 column![
                     text("FAN SPEED").size(12).style(style::TEXT_DIM),
-                    text(format!("{}%", self.current_speed)).size(36).style(style::NVIDIA_GREEN)
+                    text(match self.driver_until {
+                        Some(_) => "Auto".to_string(),
+                        None => format!("{}%", self.current_speed),
+                    }).size(36).style(style::NVIDIA_GREEN),
+                    fan_breakdown,
                 ].align_items(Alignment::Center)
             )
             .style(style::metric_card)
@@ -247,23 +587,176 @@ impl Application for NvidiaWormhole {
         ]
         .width(Length::Fill);
 
-        // 3. Curve Editor
-        let temps = [30, 50, 70, 85];
+        // 4. Curve Editor
         let mut sliders_col = column![
             text("Fan Curve Configuration").size(16)
         ].spacing(15);
         
-        for (i, &temp) in temps.iter().enumerate() {
-            let val = self.fan_speed_points[i];
-            let row_item = row![
-                text(format!("{: >3}°C", temp)).width(45).style(style::TEXT_DIM),
-                slider(0.0..=100.0, val, move |v| Message::FanPointChanged(i, v))
+        // One tab for the shared curve, one per fan of the selected GPU
+        let gpu = &self.gpus[self.selected_gpu];
+        let mut curve_tabs = row![].spacing(10);
+        let targets = std::iter::once(CurveTarget::Shared)
+            .chain((0..gpu.fans.len()).map(CurveTarget::Fan));
+        for target in targets {
+            let label = match target {
+                CurveTarget::Shared => text("All fans").size(14),
+                CurveTarget::Fan(i) => text(format!("Fan {}", i)).size(14),
+            };
+            let tab = if target == self.curve_target {
+                button(label).padding([6, 14])
+            } else {
+                button(label).on_press(Message::SelectCurve(target)).padding([6, 14])
+            };
+            curve_tabs = curve_tabs.push(tab);
+        }
+        sliders_col = sliders_col.push(curve_tabs);
+        if let Some(target) = self.pid_target {
+            sliders_col = sliders_col.push(
+                text(format!("PID mode is holding {}°C; the curves below are not in use", target))
+                    .size(12)
+                    .style(style::TEXT_DIM)
+            );
+        }
+        if self.config.mode == ControlMode::Formula {
+            sliders_col = sliders_col.push(
+                text("Formula mode is on; the curves below only stand in when the formula fails")
+                    .size(12)
+                    .style(style::TEXT_DIM)
+            );
+        }
+        
+        let mut editable = true;
+        if self.curve_target == CurveTarget::Shared && self.config.gpu_config(gpu).is_some_and(|g| g.curve.is_some()) {
+            sliders_col = sliders_col.push(
+                text("This GPU has its own curve instead of the shared one").size(12).style(style::TEXT_DIM)
+            );
+        }
+        if let CurveTarget::Fan(fan) = self.curve_target {
+            let fan_config = self.config.fan_config(gpu, fan);
+            let has_curve = fan_config.is_some_and(|f| f.curve.is_some());
+            let offset = fan_config.map(|f| f.offset).unwrap_or(0) as f64;
+            editable = has_curve;
+            
+            sliders_col = sliders_col.push(
+                checkbox("Own curve for this fan", has_curve).on_toggle(Message::ToggleFanCurve)
+            );
+            sliders_col = sliders_col.push(
+                row![
+                    text("Offset").width(45).style(style::TEXT_DIM),
+                    slider(-50.0..=50.0, offset, Message::FanOffsetChanged)
+                        .step(1.0)
+                        .width(Length::Fill),
+                    text(format!("{:+.0}%", offset)).width(45).style(style::NVIDIA_GREEN),
+                ]
+                .spacing(15)
+                .align_items(Alignment::Center)
+            );
+            if !has_curve {
+                sliders_col = sliders_col.push(
+                    text("Follows the shared curve").size(12).style(style::TEXT_DIM)
+                );
+            }
+            if let Some(calibration) = fan_config.and_then(|f| f.calibration.as_ref()) {
+                let top = calibration.rpm.last().map(|&(_, rpm)| rpm).unwrap_or(0);
+                sliders_col = sliders_col.push(
+                    text(format!(
+                        "Calibrated: starts at {}% (lower speeds other than 0% are raised to it), up to {} RPM",
+                        calibration.start_duty, top
+                    ))
+                    .size(12)
+                    .style(style::TEXT_DIM)
+                );
+            }
+        }
+        
+        // The curve as the daemon applies it, with its interpolation and input
+        let (points, interpolation, input) = match self.curve_target {
+            CurveTarget::Shared => (
+                self.config.curve_for(gpu).to_vec(),
+                self.config.interpolation_for(gpu),
+                self.config.input_for(gpu),
+            ),
+            CurveTarget::Fan(fan) => (
+                self.config.fan_curve_for(gpu, fan).to_vec(),
+                self.config.fan_interpolation_for(gpu, fan),
+                self.config.fan_input_for(gpu, fan),
+            ),
+        };
+        if input != CurveInput::CoreTemp {
+            sliders_col = sliders_col.push(
+                text(format!("This curve follows {} ({})", input, input.unit()))
+                    .size(12)
+                    .style(style::TEXT_DIM)
+            );
+        }
+        let mut shapes = row![text("Shape").width(45).style(style::TEXT_DIM)].spacing(10).align_items(Alignment::Center);
+        for (mode, label) in [
+            (Interpolation::Step, "Step"),
+            (Interpolation::Linear, "Linear"),
+            (Interpolation::MonotoneCubic, "Smooth"),
+        ] {
+            let tab = button(text(label).size(14)).padding([4, 12]);
+            shapes = shapes.push(if editable && mode != interpolation {
+                tab.on_press(Message::SelectInterpolation(mode))
+            } else {
+                tab
+            });
+        }
+        sliders_col = sliders_col.push(shapes);
+        let (min_speed, max_speed) = self.fan_ranges.get(self.selected_gpu).copied().unwrap_or((0, 100));
+        if (min_speed, max_speed) != (0, 100) {
+            sliders_col = sliders_col.push(
+                text(format!(
+                    "This card runs its fans between {}% and {}%; the driver moves other settings into that range",
+                    min_speed, max_speed
+                ))
+                .size(12)
+                .style(style::TEXT_DIM)
+            );
+        }
+        sliders_col = sliders_col.push(
+            // The temperature marker means nothing on other inputs
+            canvas(CurveView {
+                x_range: CurveView::x_range(&points, &input),
+                points: points.clone(),
+                interpolation,
+                current_temp: (input == CurveInput::CoreTemp).then_some(self.current_temp),
+                speed_range: (min_speed, max_speed),
+            })
+                .width(Length::Fill)
+                .height(Length::Fixed(140.0))
+        );
+        
+        // Speeds are shown in RPM too once the fan is calibrated
+        let calibration = match self.curve_target {
+            CurveTarget::Shared => None,
+            CurveTarget::Fan(fan) => self.config.fan_calibration(gpu, fan),
+        };
+        // One slider per point, labelled in the curve's own unit
+        for (i, &(x, speed)) in points.iter().enumerate() {
+            let val = self.fan_speed_points.get(i).copied().unwrap_or(speed as f64);
+            // Inherited curves are shown but not editable from the fan's tab
+            let control: Element<'_, Message> = if editable {
+                // Only the speeds the card can run at are offered
+                slider(min_speed as f64..=max_speed as f64, val, move |v| Message::FanPointChanged(i, v))
                     .step(1.0)
-                    .width(Length::Fill),
+                    .width(Length::Fill)
+                    .into()
+            } else {
+                Space::with_width(Length::Fill).into()
+            };
+            let mut row_item = row![
+                text(format!("{: >3}{}", x, input.unit())).width(45).style(style::TEXT_DIM),
+                control,
                 text(format!("{: >3.0}%", val)).width(45).style(style::NVIDIA_GREEN),
             ]
             .spacing(15)
             .align_items(Alignment::Center);
+            if let Some(calibration) = calibration {
+                row_item = row_item.push(
+                    text(format!("≈{} RPM", calibrate::expected_rpm(calibration, val as u32))).width(80).style(style::TEXT_DIM)
+                );
+            }
             
             sliders_col = sliders_col.push(row_item);
         }
@@ -273,7 +766,7 @@ impl Application for NvidiaWormhole {
             .width(Length::Fill)
             .style(style::card);
 
-        // 4. Settings
+        // 5. Settings
         let autostart_checkbox = checkbox(
             "Start daemon on login",
             self.autostart_enabled,
@@ -284,10 +777,21 @@ impl Application for NvidiaWormhole {
             None => Space::with_height(0).into(),
         };
 
+        // Needs the fans to itself, and a way to write them
+        let calibrate_button = button(text(if self.calibrating { "Calibrating..." } else { "Calibrate fans" }).size(14))
+            .padding([6, 14]);
+        let calibrate_row = row![
+            if self.calibrating || !self.has_permissions { calibrate_button } else { calibrate_button.on_press(Message::Calibrate) },
+            text("Measures each fan's RPM while the GPU is idle; takes a few minutes").size(12).style(style::TEXT_DIM),
+        ]
+        .spacing(15)
+        .align_items(Alignment::Center);
+
         let settings_panel = container(
             column![
                 text("Settings").size(16),
                 autostart_checkbox,
+                calibrate_row,
                 setup_status,
             ]
             .spacing(10)
@@ -296,7 +800,7 @@ impl Application for NvidiaWormhole {
         .width(Length::Fill)
         .style(style::card);
 
-        // 5. Footer
+        // 6. Footer
         let footer = container(
             row![
                 button(text("⭐ GitHub").size(14))
@@ -317,8 +821,12 @@ impl Application for NvidiaWormhole {
 
         container(
             column![
-                text(&self.gpu_name).size(24).style(style::NVIDIA_GREEN),
+                text(&self.gpus[self.selected_gpu].name).size(24).style(style::NVIDIA_GREEN),
+                gpu_selector,
                 permission_warning,
+                fail_safe_warning,
+                fan_failure_warning,
+                critical_warning,
                 Space::with_height(10),
                 dashboard,
                 Space::with_height(10),
@@ -337,3 +845,46 @@ impl Application for NvidiaWormhole {
         .into()
     }
 }
+
+/// Fan speeds each GPU accepts, the full range where the card can't tell
+fn fan_ranges(backend: &dyn GpuBackend, gpus: &[Gpu]) -> Vec<(u32, u32)> {
+    gpus.iter()
+        .map(|gpu| backend.fan_speed_range(gpu.id).ok().filter(|(min, max)| min <= max && *max <= 100).unwrap_or((0, 100)))
+        .collect()
+}
+
+impl NvidiaWormhole {
+    /// Push the edited config to the daemon and persist it
+    fn apply_config(&mut self) {
+        if let Ok(mut config) = self.daemon_state.config.lock() {
+            *config = self.config.clone();
+        }
+        if let Err(e) = self.config.save() {
+            log::error!("Failed to save config: {}", e);
+        }
+    }
+
+    /// Refresh the sliders from the curve currently being edited
+    fn load_curve_points(&mut self) {
+        self.fan_speed_points = match self.curve_target {
+            CurveTarget::Shared => config::curve_speeds_f64(self.config.curve_for(&self.gpus[self.selected_gpu])),
+            CurveTarget::Fan(fan) => {
+                config::curve_speeds_f64(self.config.fan_curve_for(&self.gpus[self.selected_gpu], fan))
+            }
+        };
+    }
+
+    fn show_status(&mut self, gpu: &GpuStatus) {
+        self.current_temp = gpu.current_temp;
+        self.current_speed = gpu.current_speed;
+        self.current_usage = gpu.gpu_usage;
+        self.fan_speeds = gpu.fan_speeds.clone();
+        self.actual_speeds = gpu.actual_speeds.clone();
+        self.fan_rpms = gpu.fan_rpms.clone();
+        self.failed_fans = gpu.failed_fans.clone();
+        self.fail_safe = gpu.fail_safe.clone();
+        self.critical = gpu.critical;
+        self.pid_target = gpu.pid_target;
+        self.driver_until = gpu.driver_until;
+    }
+}
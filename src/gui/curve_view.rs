@@ -0,0 +1,121 @@
+//! Read-only plot of a fan curve, drawn with the curve's own interpolation
+
+use iced::widget::canvas::{self, Frame, Geometry, Path, Stroke};
+use iced::{mouse, Color, Point, Rectangle, Renderer, Size, Theme};
+
+use crate::config::{CurveInput, Interpolation};
+use crate::daemon::math;
+use crate::gui::style;
+
+/// Temperature range shown on the x axis
+const MIN_TEMP: f64 = 20.0;
+const MAX_TEMP: f64 = 100.0;
+const PADDING: f32 = 8.0;
+/// Vertical grid lines split the x axis into this many parts
+const X_DIVISIONS: u32 = 4;
+const SAMPLES: u32 = 1000;
+
+pub struct CurveView {
+    pub points: Vec<(u32, u32)>,
+    pub interpolation: Interpolation,
+    /// Shown on the x axis, in the unit of the curve's input
+    pub x_range: (f64, f64),
+    /// Marked with a vertical line
+    pub current_temp: Option<u32>,
+    /// Speeds the card accepts; the rest of the plot is greyed out
+    pub speed_range: (u32, u32),
+}
+
+impl CurveView {
+    /// Range that fits a curve on the given input: 20–100°C, 0–100% or
+    /// 0 W up to a bit past the last point, widened to include every point
+    pub fn x_range(points: &[(u32, u32)], input: &CurveInput) -> (f64, f64) {
+        let last = points.iter().map(|&(x, _)| x).max().unwrap_or(0) as f64;
+        let (min, max) = match input {
+            CurveInput::Usage => (0.0, 100.0),
+            CurveInput::PowerDraw => (0.0, (last * 1.25 / 50.0).ceil().max(1.0) * 50.0),
+            _ => (MIN_TEMP, MAX_TEMP),
+        };
+        let first = points.iter().map(|&(x, _)| x).min().unwrap_or(0) as f64;
+        (min.min(first), max.max(last))
+    }
+
+    fn to_screen(&self, size: Size, value: f64, speed: f64) -> Point {
+        let width = size.width - 2.0 * PADDING;
+        let height = size.height - 2.0 * PADDING;
+        let (min, max) = self.x_range;
+        let x = (value - min) / (max - min).max(1.0);
+        Point::new(
+            PADDING + x.clamp(0.0, 1.0) as f32 * width,
+            PADDING + (1.0 - speed.clamp(0.0, 100.0) / 100.0) as f32 * height,
+        )
+    }
+}
+
+impl<Message> canvas::Program<Message> for CurveView {
+    type State = ();
+
+    fn draw(
+        &self,
+        _state: &(),
+        renderer: &Renderer,
+        _theme: &Theme,
+        bounds: Rectangle,
+        _cursor: mouse::Cursor,
+    ) -> Vec<Geometry> {
+        let mut frame = Frame::new(renderer, bounds.size());
+        let size = frame.size();
+        let grid = Stroke::default().with_color(Color::from_rgb(0.25, 0.25, 0.25)).with_width(1.0);
+
+        // Every 25%, and every 20°C on the default temperature range
+        let (min, max) = self.x_range;
+        for speed in (0..=100).step_by(25) {
+            let path = Path::line(
+                self.to_screen(size, min, speed as f64),
+                self.to_screen(size, max, speed as f64),
+            );
+            frame.stroke(&path, grid.clone());
+        }
+        for i in 0..=X_DIVISIONS {
+            let value = min + (max - min) * i as f64 / X_DIVISIONS as f64;
+            let path = Path::line(self.to_screen(size, value, 0.0), self.to_screen(size, value, 100.0));
+            frame.stroke(&path, grid.clone());
+        }
+
+        let unusable = Color::from_rgba(0.5, 0.5, 0.5, 0.15);
+        let (min_speed, max_speed) = self.speed_range;
+        for (from, to) in [(0, min_speed), (max_speed, 100)] {
+            if from < to {
+                let top_left = self.to_screen(size, min, to as f64);
+                let bottom_right = self.to_screen(size, max, from as f64);
+                frame.fill_rectangle(top_left, Size::new(bottom_right.x - top_left.x, bottom_right.y - top_left.y), unusable);
+            }
+        }
+
+        if let Some(temp) = self.current_temp.map(f64::from).filter(|t| (min..=max).contains(t)) {
+            let path = Path::line(self.to_screen(size, temp, 0.0), self.to_screen(size, temp, 100.0));
+            frame.stroke(&path, Stroke::default().with_color(style::TEXT_DIM).with_width(1.0));
+        }
+
+        // Sample finely enough that step edges look vertical
+        let curve = Path::new(|builder| {
+            for i in 0..=SAMPLES {
+                let value = min + (max - min) * i as f64 / SAMPLES as f64;
+                let point = self.to_screen(size, value, math::interpolate(value, &self.points, self.interpolation));
+                if i == 0 {
+                    builder.move_to(point);
+                } else {
+                    builder.line_to(point);
+                }
+            }
+        });
+        frame.stroke(&curve, Stroke::default().with_color(style::NVIDIA_GREEN).with_width(2.0));
+
+        for &(value, speed) in &self.points {
+            let dot = Path::circle(self.to_screen(size, value as f64, speed as f64), 3.5);
+            frame.fill(&dot, Color::WHITE);
+        }
+
+        vec![frame.into_geometry()]
+    }
+}
@@ -1,2 +1,3 @@
 pub mod style;
 pub mod app;
+pub mod curve_view;
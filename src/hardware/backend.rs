@@ -0,0 +1,55 @@
+//! Hardware abstraction for the control loop
+//! Lets the daemon run against any source of GPU sensors and fan outputs
+
+use anyhow::Result;
+
+use super::probe::Gpu;
+
+/// Everything the daemon needs from the hardware layer.
+/// Implementations must be shareable across the GUI and daemon threads.
+pub trait GpuBackend: Send + Sync {
+    /// Short human readable name, used in logs
+    fn name(&self) -> &'static str;
+
+    /// List the GPUs and their fans
+    fn detect_gpus(&self) -> Result<Vec<Gpu>>;
+
+    /// Core temperature in °C
+    fn get_temp(&self, gpu_id: u32) -> Result<u32>;
+
+    /// GPU utilization in percent
+    fn get_gpu_usage(&self, gpu: &Gpu) -> Result<u32>;
+
+    /// Board power draw in watts
+    fn get_power_draw(&self, gpu: &Gpu) -> Result<u32>;
+
+    /// Memory (VRAM) temperature in °C; many consumer cards don't report it
+    fn get_memory_temp(&self, gpu: &Gpu) -> Result<u32>;
+
+    /// Lowest and highest fan speed the card accepts, in percent.
+    /// The driver quietly raises or lowers writes outside this range.
+    fn fan_speed_range(&self, gpu_id: u32) -> Result<(u32, u32)>;
+
+    /// Speed a fan is actually running at, in percent, whoever set it
+    fn get_fan_speed(&self, gpu_id: u32, fan_id: u32) -> Result<u32>;
+
+    /// Speed a fan is actually running at, in RPM
+    fn get_fan_rpm(&self, gpu_id: u32, fan_id: u32) -> Result<u32>;
+
+    /// Percent and, when `with_rpm` is set, RPM of one fan; None where a reading isn't available.
+    /// Backends that spawn a process per query should read both in one go.
+    fn read_fan(&self, gpu_id: u32, fan_id: u32, with_rpm: bool) -> (Option<u32>, Option<u32>) {
+        let speed = self.get_fan_speed(gpu_id, fan_id).ok();
+        let rpm = if with_rpm { self.get_fan_rpm(gpu_id, fan_id).ok() } else { None };
+        (speed, rpm)
+    }
+
+    /// Put the GPU in manual mode and set a fan's target speed in percent
+    fn set_fan_speed(&self, gpu_id: u32, fan_id: u32, speed: u32) -> Result<()>;
+
+    /// Hand the GPU's fans back to the driver's automatic control
+    fn restore_auto(&self, gpu: &Gpu) -> Result<()>;
+
+    /// Cap the board power in watts, or go back to the default limit with None
+    fn set_power_limit(&self, gpu: &Gpu, watts: Option<u32>) -> Result<()>;
+}
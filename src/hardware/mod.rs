@@ -1,3 +1,29 @@
+pub mod backend;
 pub mod nvidia;
+pub mod nvml;
 pub mod probe;
+pub mod sensors;
+pub mod simulated;
 pub mod telemetry;
+
+use std::sync::Arc;
+
+use crate::config::Config;
+use backend::GpuBackend;
+
+/// Pick the hardware backend to use for this run
+pub fn select_backend(config: &Config, simulate: bool) -> Arc<dyn GpuBackend> {
+    let backend: Arc<dyn GpuBackend> = if simulate {
+        Arc::new(simulated::Simulated::new(config.simulation.clone()))
+    } else {
+        match nvml::Nvml::load() {
+            Ok(nvml) => Arc::new(nvml),
+            Err(e) => {
+                log::warn!("NVML unavailable ({:#}), falling back to nvidia-settings", e);
+                Arc::new(nvidia::NvidiaSettings)
+            }
+        }
+    };
+    log::info!("Using {} backend", backend.name());
+    backend
+}
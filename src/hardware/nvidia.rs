@@ -1,6 +1,77 @@
 use anyhow::{Result, Context};
 
 use crate::util::run_host_command;
+use super::backend::GpuBackend;
+use super::probe::{self, Gpu};
+
+/// Backend that shells out to `nvidia-settings` and `nvidia-smi`
+pub struct NvidiaSettings;
+
+impl GpuBackend for NvidiaSettings {
+    fn name(&self) -> &'static str {
+        "nvidia-settings"
+    }
+
+    fn detect_gpus(&self) -> Result<Vec<Gpu>> {
+        probe::detect_gpus()
+    }
+
+    fn get_temp(&self, gpu_id: u32) -> Result<u32> {
+        get_temp(gpu_id)
+    }
+
+    fn get_gpu_usage(&self, gpu: &Gpu) -> Result<u32> {
+        get_gpu_usage(gpu)
+    }
+
+    fn get_power_draw(&self, gpu: &Gpu) -> Result<u32> {
+        get_power_draw(gpu)
+    }
+
+    fn get_memory_temp(&self, gpu: &Gpu) -> Result<u32> {
+        get_memory_temp(gpu)
+    }
+
+    fn fan_speed_range(&self, _gpu_id: u32) -> Result<(u32, u32)> {
+        // Neither nvidia-settings nor nvidia-smi report the range; only NVML does
+        Ok((0, 100))
+    }
+
+    fn get_fan_speed(&self, _gpu_id: u32, fan_id: u32) -> Result<u32> {
+        get_fan_speed(fan_id)
+    }
+
+    fn get_fan_rpm(&self, _gpu_id: u32, fan_id: u32) -> Result<u32> {
+        get_fan_rpm(fan_id)
+    }
+
+    fn read_fan(&self, _gpu_id: u32, fan_id: u32, with_rpm: bool) -> (Option<u32>, Option<u32>) {
+        if !with_rpm {
+            return (get_fan_speed(fan_id).ok(), None);
+        }
+        // One process for both; if the card has no RPM sensor the whole query fails
+        match query_fan(fan_id, &["GPUCurrentFanSpeed", "GPUCurrentFanSpeedRPM"]) {
+            Ok(values) => (values.first().copied(), values.get(1).copied()),
+            Err(_) => (get_fan_speed(fan_id).ok(), None),
+        }
+    }
+
+    fn set_fan_speed(&self, gpu_id: u32, fan_id: u32, speed: u32) -> Result<()> {
+        set_fan_speed(gpu_id, fan_id, speed)
+    }
+
+    fn restore_auto(&self, gpu: &Gpu) -> Result<()> {
+        // Its [gpu:N] is unknown, so it was never taken over either
+        if gpu.unmapped {
+            return Ok(());
+        }
+        restore_auto(gpu.id)
+    }
+
+    fn set_power_limit(&self, gpu: &Gpu, watts: Option<u32>) -> Result<()> {
+        set_power_limit(gpu, watts)
+    }
+}
 
 pub fn set_fan_speed(gpu_id: u32, fan_id: u32, speed: u32) -> Result<()> {
     let assignment1 = format!("[gpu:{}]/GPUFanControlState=1", gpu_id);
@@ -15,6 +86,34 @@ pub fn set_fan_speed(gpu_id: u32, fan_id: u32, speed: u32) -> Result<()> {
     run_host_command("sudo", &args).map(|_| ())
 }
 
+/// Give fan control back to the driver
+pub fn restore_auto(gpu_id: u32) -> Result<()> {
+    let assignment = format!("[gpu:{}]/GPUFanControlState=0", gpu_id);
+    run_host_command("sudo", &["nvidia-settings", "-a", &assignment]).map(|_| ())
+}
+
+/// How to address a GPU with `nvidia-smi -i`. nvidia-smi numbers GPUs in PCI order,
+/// not like nvidia-settings' `[gpu:N]`, so never by index.
+fn smi_target(gpu: &Gpu) -> Result<String> {
+    gpu.uuid.clone()
+        .or_else(|| gpu.pci_bus_id.clone())
+        .with_context(|| format!("GPU {} has no UUID or PCI bus ID to find it by in nvidia-smi", gpu.id))
+}
+
+/// Set the power limit with `nvidia-smi -pl`; None restores the card's default limit
+pub fn set_power_limit(gpu: &Gpu, watts: Option<u32>) -> Result<()> {
+    let target = smi_target(gpu)?;
+    let watts = match watts {
+        Some(watts) => watts.to_string(),
+        None => {
+            let args = ["--query-gpu=power.default_limit", "--format=csv,noheader,nounits", "-i", &target];
+            let output = run_host_command("nvidia-smi", &args)?;
+            output.trim().to_string()
+        }
+    };
+    run_host_command("sudo", &["nvidia-smi", "-i", &target, "-pl", &watts]).map(|_| ())
+}
+
 pub fn get_temp(gpu_id: u32) -> Result<u32> {
     let query = format!("[gpu:{}]/GPUCoreTemp", gpu_id);
     let args = vec![
@@ -27,14 +126,48 @@ pub fn get_temp(gpu_id: u32) -> Result<u32> {
     temp_str.parse::<u32>().context("Failed to parse temperature")
 }
 
-pub fn get_gpu_usage(gpu_id: u32) -> Result<u32> {
-    // nvidia-smi --query-gpu=utilization.gpu --format=csv,noheader,nounits -i <id>
+/// Integer fan attributes, e.g. `GPUCurrentFanSpeed`, for a global fan index,
+/// all in one nvidia-settings call
+fn query_fan(fan_id: u32, attributes: &[&str]) -> Result<Vec<u32>> {
+    let queries: Vec<String> = attributes.iter().map(|attribute| format!("[fan:{}]/{}", fan_id, attribute)).collect();
+    let mut args = Vec::with_capacity(2 * queries.len() + 1);
+    for query in &queries {
+        args.extend(["-q", query.as_str()]);
+    }
+    args.push("-t");
+    let output = run_host_command("nvidia-settings", &args)?;
+    parse_values(&output, attributes)
+}
+
+/// Terse output has one value per line, in the order of the queries
+fn parse_values(output: &str, attributes: &[&str]) -> Result<Vec<u32>> {
+    let mut lines = output.lines().map(str::trim).filter(|line| !line.is_empty());
+    attributes
+        .iter()
+        .map(|attribute| {
+            lines.next()
+                .and_then(|line| line.parse::<u32>().ok())
+                .with_context(|| format!("Failed to parse {}", attribute))
+        })
+        .collect()
+}
+
+pub fn get_fan_speed(fan_id: u32) -> Result<u32> {
+    Ok(query_fan(fan_id, &["GPUCurrentFanSpeed"])?[0])
+}
+
+pub fn get_fan_rpm(fan_id: u32) -> Result<u32> {
+    Ok(query_fan(fan_id, &["GPUCurrentFanSpeedRPM"])?[0])
+}
+
+pub fn get_gpu_usage(gpu: &Gpu) -> Result<u32> {
+    // nvidia-smi --query-gpu=utilization.gpu --format=csv,noheader,nounits -i <uuid>
     // Output: just a number like "15"
-    let gpu_idx = gpu_id.to_string();
+    let target = smi_target(gpu)?;
     let args = vec![
         "--query-gpu=utilization.gpu",
         "--format=csv,noheader,nounits",
-        "-i", &gpu_idx
+        "-i", &target
     ];
     
     let output = run_host_command("nvidia-smi", &args)?;
@@ -42,9 +175,54 @@ pub fn get_gpu_usage(gpu_id: u32) -> Result<u32> {
     usage_str.parse::<u32>().context("Failed to parse GPU usage from nvidia-smi")
 }
 
+/// One numeric field from `nvidia-smi --query-gpu`, rounded; "[N/A]" is an error
+fn query_smi(gpu: &Gpu, field: &str) -> Result<u32> {
+    let query = format!("--query-gpu={}", field);
+    let target = smi_target(gpu)?;
+    let output = run_host_command("nvidia-smi", &[&query, "--format=csv,noheader,nounits", "-i", &target])?;
+    let value: f64 = output
+        .trim()
+        .parse()
+        .with_context(|| format!("nvidia-smi reports {} as {:?}", field, output.trim()))?;
+    Ok(value.round().max(0.0) as u32)
+}
+
+pub fn get_power_draw(gpu: &Gpu) -> Result<u32> {
+    query_smi(gpu, "power.draw")
+}
+
+pub fn get_memory_temp(gpu: &Gpu) -> Result<u32> {
+    query_smi(gpu, "temperature.memory")
+}
+
 #[cfg(test)]
 mod tests {
-    use super::*;
+    use super::{parse_values, smi_target};
+    use crate::hardware::probe::Gpu;
+
+    #[test]
+    fn test_smi_target() {
+        // nvidia-settings lists the cards in the opposite order to nvidia-smi,
+        // so [gpu:0] is nvidia-smi's GPU 1; only the UUID or bus ID finds it
+        let gpus = [
+            Gpu { id: 0, uuid: Some("GPU-9a8b7c6d-1111".into()), pci_bus_id: Some("00000000:02:00.0".into()), ..Default::default() },
+            Gpu { id: 1, uuid: Some("GPU-5d1f0c4e-1111".into()), pci_bus_id: Some("00000000:01:00.0".into()), ..Default::default() },
+        ];
+        assert_eq!(smi_target(&gpus[0]).unwrap(), "GPU-9a8b7c6d-1111");
+        assert_eq!(smi_target(&gpus[1]).unwrap(), "GPU-5d1f0c4e-1111");
+
+        let bus_only = Gpu { id: 0, pci_bus_id: Some("00000000:02:00.0".into()), ..Default::default() };
+        assert_eq!(smi_target(&bus_only).unwrap(), "00000000:02:00.0");
+        assert!(smi_target(&Gpu { id: 0, ..Default::default() }).is_err());
+    }
+
+    #[test]
+    fn test_parse_values() {
+        let attributes = ["GPUCurrentFanSpeed", "GPUCurrentFanSpeedRPM"];
+        assert_eq!(parse_values("45\n1320\n", &attributes).unwrap(), vec![45, 1320]);
+        assert!(parse_values("45\n", &attributes).is_err());
+        assert!(parse_values("45\nN/A\n", &attributes).is_err());
+    }
 
     #[test]
     fn test_fan_command_structure() {
@@ -0,0 +1,409 @@
+//! NVML backend
+//! Loads libnvidia-ml at runtime so the binary still starts on machines without it,
+//! and talks to the driver directly instead of spawning a process every tick.
+
+use std::ffi::{c_char, c_int, c_uint, c_void, CStr};
+use std::path::Path;
+use anyhow::{Result, Context, bail};
+use libloading::Library;
+use once_cell::sync::OnceCell;
+
+use super::backend::GpuBackend;
+use super::nvidia;
+use super::probe::{self, Gpu};
+
+/// Soname shipped with the NVIDIA driver
+pub const LIBRARY_NAME: &str = "libnvidia-ml.so.1";
+
+type NvmlReturn = c_int;
+type NvmlDevice = *mut c_void;
+
+const NVML_SUCCESS: NvmlReturn = 0;
+const NVML_ERROR_NO_PERMISSION: NvmlReturn = 4;
+const NVML_TEMPERATURE_GPU: c_uint = 0;
+const NVML_DEVICE_NAME_BUFFER_SIZE: usize = 96;
+const NVML_DEVICE_UUID_BUFFER_SIZE: usize = 96;
+
+#[repr(C)]
+#[derive(Default)]
+struct NvmlUtilization {
+    gpu: c_uint,
+    memory: c_uint,
+}
+
+/// nvmlPciInfo_t as used by nvmlDeviceGetPciInfo_v3; only `bus_id` is read,
+/// the rest is there to match the C layout
+#[repr(C)]
+#[allow(dead_code)]
+struct NvmlPciInfo {
+    bus_id_legacy: [c_char; 16],
+    domain: c_uint,
+    bus: c_uint,
+    device: c_uint,
+    pci_device_id: c_uint,
+    pci_sub_system_id: c_uint,
+    bus_id: [c_char; 32],
+}
+
+/// Function pointers resolved from the library.
+/// They stay valid for as long as `_lib` is loaded.
+struct Api {
+    init: unsafe extern "C" fn() -> NvmlReturn,
+    shutdown: unsafe extern "C" fn() -> NvmlReturn,
+    error_string: unsafe extern "C" fn(NvmlReturn) -> *const c_char,
+    device_get_count: unsafe extern "C" fn(*mut c_uint) -> NvmlReturn,
+    device_get_handle_by_index: unsafe extern "C" fn(c_uint, *mut NvmlDevice) -> NvmlReturn,
+    device_get_name: unsafe extern "C" fn(NvmlDevice, *mut c_char, c_uint) -> NvmlReturn,
+    device_get_uuid: unsafe extern "C" fn(NvmlDevice, *mut c_char, c_uint) -> NvmlReturn,
+    device_get_pci_info: unsafe extern "C" fn(NvmlDevice, *mut NvmlPciInfo) -> NvmlReturn,
+    device_get_temperature: unsafe extern "C" fn(NvmlDevice, c_uint, *mut c_uint) -> NvmlReturn,
+    device_get_utilization_rates: unsafe extern "C" fn(NvmlDevice, *mut NvmlUtilization) -> NvmlReturn,
+    device_get_power_usage: unsafe extern "C" fn(NvmlDevice, *mut c_uint) -> NvmlReturn,
+    device_get_min_max_fan_speed: unsafe extern "C" fn(NvmlDevice, *mut c_uint, *mut c_uint) -> NvmlReturn,
+    device_get_fan_speed: unsafe extern "C" fn(NvmlDevice, c_uint, *mut c_uint) -> NvmlReturn,
+    device_get_num_fans: unsafe extern "C" fn(NvmlDevice, *mut c_uint) -> NvmlReturn,
+    device_set_fan_speed: unsafe extern "C" fn(NvmlDevice, c_uint, c_uint) -> NvmlReturn,
+    device_set_default_fan_speed: unsafe extern "C" fn(NvmlDevice, c_uint) -> NvmlReturn,
+}
+
+/// Backend that calls NVML through a dynamically loaded libnvidia-ml
+pub struct Nvml {
+    api: Api,
+    /// nvidia-settings' view of the GPUs, for mapping fans when NVML writes are refused
+    settings_gpus: OnceCell<Vec<Gpu>>,
+    _lib: Library,
+}
+
+impl Nvml {
+    /// Load the driver's NVML library and initialize it
+    pub fn load() -> Result<Self> {
+        Self::open(LIBRARY_NAME)
+    }
+
+    /// Load NVML from a specific path or soname
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        // SAFETY: loading NVML runs no initializers beyond the usual ELF constructors
+        let lib = unsafe { Library::new(path) }
+            .with_context(|| format!("Failed to load {}", path.display()))?;
+
+        // SAFETY: the signatures match nvml.h for each symbol
+        let api = unsafe {
+            Api {
+                init: *lib.get(b"nvmlInit_v2\0")?,
+                shutdown: *lib.get(b"nvmlShutdown\0")?,
+                error_string: *lib.get(b"nvmlErrorString\0")?,
+                device_get_count: *lib.get(b"nvmlDeviceGetCount_v2\0")?,
+                device_get_handle_by_index: *lib.get(b"nvmlDeviceGetHandleByIndex_v2\0")?,
+                device_get_name: *lib.get(b"nvmlDeviceGetName\0")?,
+                device_get_uuid: *lib.get(b"nvmlDeviceGetUUID\0")?,
+                device_get_pci_info: *lib.get(b"nvmlDeviceGetPciInfo_v3\0")?,
+                device_get_temperature: *lib.get(b"nvmlDeviceGetTemperature\0")?,
+                device_get_utilization_rates: *lib.get(b"nvmlDeviceGetUtilizationRates\0")?,
+                device_get_power_usage: *lib.get(b"nvmlDeviceGetPowerUsage\0")?,
+                device_get_min_max_fan_speed: *lib.get(b"nvmlDeviceGetMinMaxFanSpeed\0")?,
+                device_get_fan_speed: *lib.get(b"nvmlDeviceGetFanSpeed_v2\0")?,
+                device_get_num_fans: *lib.get(b"nvmlDeviceGetNumFans\0")?,
+                device_set_fan_speed: *lib.get(b"nvmlDeviceSetFanSpeed_v2\0")?,
+                device_set_default_fan_speed: *lib.get(b"nvmlDeviceSetDefaultFanSpeed_v2\0")?,
+            }
+        };
+
+        let nvml = Self { api, settings_gpus: OnceCell::new(), _lib: lib };
+        // SAFETY: no arguments, callable from any thread
+        nvml.check("nvmlInit_v2", unsafe { (nvml.api.init)() })?;
+        Ok(nvml)
+    }
+
+    fn check(&self, call: &str, ret: NvmlReturn) -> Result<()> {
+        if ret == NVML_SUCCESS {
+            return Ok(());
+        }
+
+        // SAFETY: nvmlErrorString returns a static, NUL terminated string
+        let message = unsafe {
+            let ptr = (self.api.error_string)(ret);
+            if ptr.is_null() {
+                format!("error {}", ret)
+            } else {
+                CStr::from_ptr(ptr).to_string_lossy().into_owned()
+            }
+        };
+        bail!("{} failed: {} ({})", call, message, ret);
+    }
+
+    fn device(&self, gpu_id: u32) -> Result<NvmlDevice> {
+        let mut device: NvmlDevice = std::ptr::null_mut();
+        // SAFETY: out pointer is valid for the duration of the call
+        let ret = unsafe { (self.api.device_get_handle_by_index)(gpu_id, &mut device) };
+        self.check("nvmlDeviceGetHandleByIndex_v2", ret)?;
+        Ok(device)
+    }
+
+    fn device_name(&self, device: NvmlDevice) -> Result<String> {
+        let mut buf = [0 as c_char; NVML_DEVICE_NAME_BUFFER_SIZE];
+        // SAFETY: buffer length is passed along, NVML NUL terminates within it
+        let ret = unsafe { (self.api.device_get_name)(device, buf.as_mut_ptr(), buf.len() as c_uint) };
+        self.check("nvmlDeviceGetName", ret)?;
+        // SAFETY: see above
+        Ok(unsafe { CStr::from_ptr(buf.as_ptr()) }.to_string_lossy().into_owned())
+    }
+
+    fn device_uuid(&self, device: NvmlDevice) -> Result<String> {
+        let mut buf = [0 as c_char; NVML_DEVICE_UUID_BUFFER_SIZE];
+        // SAFETY: buffer length is passed along, NVML NUL terminates within it
+        let ret = unsafe { (self.api.device_get_uuid)(device, buf.as_mut_ptr(), buf.len() as c_uint) };
+        self.check("nvmlDeviceGetUUID", ret)?;
+        // SAFETY: see above
+        Ok(unsafe { CStr::from_ptr(buf.as_ptr()) }.to_string_lossy().into_owned())
+    }
+
+    fn device_pci_bus_id(&self, device: NvmlDevice) -> Result<String> {
+        // SAFETY: nvmlPciInfo_t is plain old data, all zeroes is a valid value
+        let mut info: NvmlPciInfo = unsafe { std::mem::zeroed() };
+        // SAFETY: out pointer is valid for the duration of the call
+        let ret = unsafe { (self.api.device_get_pci_info)(device, &mut info) };
+        self.check("nvmlDeviceGetPciInfo_v3", ret)?;
+        // SAFETY: bus_id is NUL terminated within its 32 bytes
+        Ok(unsafe { CStr::from_ptr(info.bus_id.as_ptr()) }.to_string_lossy().into_owned())
+    }
+
+    fn num_fans(&self, device: NvmlDevice) -> Result<u32> {
+        let mut count: c_uint = 0;
+        // SAFETY: out pointer is valid for the duration of the call
+        let ret = unsafe { (self.api.device_get_num_fans)(device, &mut count) };
+        self.check("nvmlDeviceGetNumFans", ret)?;
+        Ok(count)
+    }
+
+    /// nvidia-settings' entry for the same card as NVML device `gpu_id`.
+    /// NVML numbers GPUs in PCI order, nvidia-settings by NV-CONTROL target,
+    /// so the indices can't be compared; the UUID or PCI bus ID can.
+    fn settings_gpu(&self, gpu_id: u32) -> Result<&Gpu> {
+        let gpus = self.settings_gpus.get_or_try_init(probe::detect_gpus)?;
+        let device = self.device(gpu_id)?;
+        let this = Gpu {
+            id: gpu_id,
+            uuid: self.device_uuid(device).ok(),
+            pci_bus_id: self.device_pci_bus_id(device).ok(),
+            ..Default::default()
+        };
+        gpus.iter()
+            .find(|gpu| !gpu.unmapped && gpu.same_card(&this))
+            .with_context(|| format!("No nvidia-settings GPU matches NVML GPU {}", gpu_id))
+    }
+
+    /// nvidia-settings numbers fans globally, NVML per device
+    fn settings_fan(&self, gpu_id: u32, fan_id: u32) -> Result<(u32, u32)> {
+        let gpu = self.settings_gpu(gpu_id)?;
+        let fan = gpu.fans.get(fan_id as usize).copied().with_context(|| {
+            format!("No nvidia-settings fan for GPU {} fan {}", gpu_id, fan_id)
+        })?;
+        Ok((gpu.id, fan))
+    }
+
+    /// Hand a fan back to the driver's automatic control
+    pub fn set_default_fan_speed(&self, gpu_id: u32, fan_id: u32) -> Result<()> {
+        let device = self.device(gpu_id)?;
+        // SAFETY: device handle comes from NVML
+        let ret = unsafe { (self.api.device_set_default_fan_speed)(device, fan_id) };
+        if ret == NVML_ERROR_NO_PERMISSION {
+            log::debug!("NVML fan reset not permitted, using nvidia-settings");
+            return nvidia::restore_auto(self.settings_gpu(gpu_id)?.id);
+        }
+        self.check("nvmlDeviceSetDefaultFanSpeed_v2", ret)
+    }
+}
+
+impl Drop for Nvml {
+    fn drop(&mut self) {
+        // SAFETY: balanced with the nvmlInit_v2 in `open`
+        unsafe {
+            (self.api.shutdown)();
+        }
+    }
+}
+
+impl GpuBackend for Nvml {
+    fn name(&self) -> &'static str {
+        "nvml"
+    }
+
+    fn detect_gpus(&self) -> Result<Vec<Gpu>> {
+        let mut count: c_uint = 0;
+        // SAFETY: out pointer is valid for the duration of the call
+        let ret = unsafe { (self.api.device_get_count)(&mut count) };
+        self.check("nvmlDeviceGetCount_v2", ret)?;
+
+        // Passively cooled and compute cards may not report a name or fans;
+        // they still belong in the list, just with nothing to drive
+        (0..count)
+            .map(|id| {
+                let device = self.device(id)?;
+                Ok(Gpu {
+                    id,
+                    name: self.device_name(device).unwrap_or_else(|_| format!("NVIDIA GPU {}", id)),
+                    uuid: self.device_uuid(device).ok(),
+                    pci_bus_id: self.device_pci_bus_id(device).ok(),
+                    fans: (0..self.num_fans(device).unwrap_or(0)).collect(),
+                    ..Default::default()
+                })
+            })
+            .collect()
+    }
+
+    fn get_temp(&self, gpu_id: u32) -> Result<u32> {
+        let device = self.device(gpu_id)?;
+        let mut temp: c_uint = 0;
+        // SAFETY: out pointer is valid for the duration of the call
+        let ret = unsafe { (self.api.device_get_temperature)(device, NVML_TEMPERATURE_GPU, &mut temp) };
+        self.check("nvmlDeviceGetTemperature", ret)?;
+        Ok(temp)
+    }
+
+    fn get_gpu_usage(&self, gpu: &Gpu) -> Result<u32> {
+        let device = self.device(gpu.id)?;
+        let mut util = NvmlUtilization::default();
+        // SAFETY: out pointer is valid for the duration of the call
+        let ret = unsafe { (self.api.device_get_utilization_rates)(device, &mut util) };
+        self.check("nvmlDeviceGetUtilizationRates", ret)?;
+        Ok(util.gpu)
+    }
+
+    fn get_power_draw(&self, gpu: &Gpu) -> Result<u32> {
+        let device = self.device(gpu.id)?;
+        let mut milliwatts: c_uint = 0;
+        // SAFETY: out pointer is valid for the duration of the call
+        let ret = unsafe { (self.api.device_get_power_usage)(device, &mut milliwatts) };
+        self.check("nvmlDeviceGetPowerUsage", ret)?;
+        Ok((milliwatts + 500) / 1000)
+    }
+
+    fn get_memory_temp(&self, gpu: &Gpu) -> Result<u32> {
+        // Only exposed through NVML's field-value API; nvidia-smi reads the same value
+        nvidia::get_memory_temp(gpu)
+    }
+
+    fn fan_speed_range(&self, gpu_id: u32) -> Result<(u32, u32)> {
+        let device = self.device(gpu_id)?;
+        let (mut min, mut max): (c_uint, c_uint) = (0, 0);
+        // SAFETY: out pointers are valid for the duration of the call
+        let ret = unsafe { (self.api.device_get_min_max_fan_speed)(device, &mut min, &mut max) };
+        self.check("nvmlDeviceGetMinMaxFanSpeed", ret)?;
+        Ok((min, max))
+    }
+
+    fn get_fan_speed(&self, gpu_id: u32, fan_id: u32) -> Result<u32> {
+        let device = self.device(gpu_id)?;
+        let mut speed: c_uint = 0;
+        // SAFETY: out pointer is valid for the duration of the call
+        let ret = unsafe { (self.api.device_get_fan_speed)(device, fan_id, &mut speed) };
+        self.check("nvmlDeviceGetFanSpeed_v2", ret)?;
+        Ok(speed)
+    }
+
+    fn get_fan_rpm(&self, gpu_id: u32, fan_id: u32) -> Result<u32> {
+        // NVML's RPM query needs a much newer driver; nvidia-settings has it everywhere
+        nvidia::get_fan_rpm(self.settings_fan(gpu_id, fan_id)?.1)
+    }
+
+    fn set_fan_speed(&self, gpu_id: u32, fan_id: u32, speed: u32) -> Result<()> {
+        let device = self.device(gpu_id)?;
+        // SAFETY: device handle comes from NVML
+        let ret = unsafe { (self.api.device_set_fan_speed)(device, fan_id, speed) };
+
+        // Fan writes need root through NVML; a desktop user only has the sudoers rule
+        if ret == NVML_ERROR_NO_PERMISSION {
+            log::debug!("NVML fan write not permitted, using nvidia-settings");
+            let (settings_gpu, settings_fan) = self.settings_fan(gpu_id, fan_id)?;
+            return nvidia::set_fan_speed(settings_gpu, settings_fan, speed);
+        }
+        self.check("nvmlDeviceSetFanSpeed_v2", ret)
+    }
+
+    fn restore_auto(&self, gpu: &Gpu) -> Result<()> {
+        for &fan_id in &gpu.fans {
+            self.set_default_fan_speed(gpu.id, fan_id)?;
+        }
+        Ok(())
+    }
+
+    fn set_power_limit(&self, gpu: &Gpu, watts: Option<u32>) -> Result<()> {
+        // Changing the limit needs root either way, nvidia-smi goes through sudo
+        nvidia::set_power_limit(gpu, watts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use std::process::Command;
+    use once_cell::sync::Lazy;
+
+    /// Stub libnvidia-ml built from tests/fixtures/nvml_stub.c, or None without a C compiler
+    static STUB: Lazy<Option<PathBuf>> = Lazy::new(|| {
+        let source = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/nvml_stub.c");
+        let output = std::env::temp_dir().join(format!("libnvidia-ml-stub-{}.so", std::process::id()));
+        let status = Command::new("cc")
+            .args(["-shared", "-fPIC", "-o"])
+            .arg(&output)
+            .arg(&source)
+            .status();
+
+        match status {
+            Ok(s) if s.success() => Some(output),
+            _ => {
+                eprintln!("cc not available, skipping NVML stub tests");
+                None
+            }
+        }
+    });
+
+    #[test]
+    fn test_missing_library() {
+        assert!(Nvml::open("/nonexistent/libnvidia-ml.so.1").is_err());
+    }
+
+    #[test]
+    fn test_stub_library() {
+        let Some(path) = STUB.as_ref() else { return };
+        let nvml = Nvml::open(path).unwrap();
+
+        // The passive card fails the name and fan queries without taking the others down
+        let gpus = nvml.detect_gpus().unwrap();
+        assert_eq!(gpus.len(), 3);
+        assert_eq!(gpus[2].name, "NVIDIA GPU 2");
+        assert!(gpus[2].fans.is_empty());
+        assert_eq!(gpus[0].name, "Stub GPU 0");
+        assert_eq!(gpus[1].fans, vec![0, 1]);
+        assert_eq!(gpus[1].uuid.as_deref(), Some("GPU-00000000-0000-0000-0000-000000000001"));
+        assert_eq!(gpus[1].pci_bus_id.as_deref(), Some("00000000:02:00.0"));
+
+        assert_eq!(nvml.get_temp(0).unwrap(), 45);
+        assert_eq!(nvml.get_temp(1).unwrap(), 60);
+        assert_eq!(nvml.get_gpu_usage(&gpus[1]).unwrap(), 80);
+        assert_eq!(nvml.get_power_draw(&gpus[1]).unwrap(), 150);
+        assert_eq!(nvml.fan_speed_range(0).unwrap(), (30, 100));
+
+        nvml.set_fan_speed(1, 0, 40).unwrap();
+        nvml.set_fan_speed(1, 1, 70).unwrap();
+        nvml.set_default_fan_speed(1, 0).unwrap();
+        assert_eq!(nvml.get_fan_speed(1, 1).unwrap(), 70);
+        assert_eq!(nvml.get_fan_speed(1, 0).unwrap(), 35);
+
+        // The stub exposes what it was told so we can check the calls landed
+        // SAFETY: stub_fan_speed is a plain C function defined in the fixture
+        let lib = unsafe { Library::new(path) }.unwrap();
+        let fan_speed: libloading::Symbol<unsafe extern "C" fn(c_uint, c_uint) -> c_int> =
+            unsafe { lib.get(b"stub_fan_speed\0") }.unwrap();
+        assert_eq!(unsafe { fan_speed(1, 1) }, 70);
+        assert_eq!(unsafe { fan_speed(1, 0) }, -1);
+
+        nvml.restore_auto(&gpus[1]).unwrap();
+        assert_eq!(unsafe { fan_speed(1, 1) }, -1);
+
+        let err = nvml.get_temp(5).unwrap_err().to_string();
+        assert!(err.contains("Invalid Argument"), "{}", err);
+    }
+}
@@ -3,88 +3,317 @@ use anyhow::Result;
 use crate::util::run_host_command;
 use regex::Regex;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Default)]
 pub struct Gpu {
     pub id: u32,
     pub name: String,
-    pub fans: u32,
+    /// NVIDIA UUID, e.g. "GPU-5d1f0c4e-..."; stable across slot changes
+    pub uuid: Option<String>,
+    /// PCI bus ID as reported by the driver, e.g. "00000000:01:00.0"
+    pub pci_bus_id: Option<String>,
+    /// Fan targets driven by this GPU, in the backend's own numbering
+    /// (global `[fan:N]` indices for nvidia-settings)
+    pub fans: Vec<u32>,
+    /// `id` is nvidia-smi's index, which need not be nvidia-settings' `[gpu:N]`,
+    /// so nothing may be addressed through it
+    pub unmapped: bool,
+}
+
+impl Gpu {
+    /// Whether `other` is this same card as seen by another tool, which may number
+    /// GPUs differently. Compares UUIDs, or PCI bus IDs when either lacks one.
+    pub fn same_card(&self, other: &Gpu) -> bool {
+        if let (Some(a), Some(b)) = (&self.uuid, &other.uuid) {
+            return a.eq_ignore_ascii_case(b);
+        }
+        match (
+            self.pci_bus_id.as_deref().and_then(parse_pci_bus_id),
+            other.pci_bus_id.as_deref().and_then(parse_pci_bus_id),
+        ) {
+            (Some(a), Some(b)) => a == b,
+            _ => false,
+        }
+    }
 }
 
 pub fn detect_gpus() -> Result<Vec<Gpu>> {
-    // 1. Detect GPUs first
-    // nvidia-settings -q gpus
-    // But easier might be nvidia-smi -L or parsing nvidia-settings output
-    
-    // For simplicty/robustness, let's assume single GPU for now or parse `nvidia-settings -q gpus`
-    // Sample output: "  [0] my_machine:0[gpu:0] (NVIDIA GeForce RTX 3080)"
-    
-    // Let's implement fan detection for GPU 0 first as requested "Detectar quantas fans a GPU tem"
-    
-    let gpu_id = 0; // Scanning for GPU 0
-    let fan_count = count_fans(gpu_id)?;
-    
-    Ok(vec![Gpu {
-        id: gpu_id,
-        name: "Generic NVIDIA GPU".to_string(), // TODO: Get real name
-        fans: fan_count,
-    }])
+    // Prefer nvidia-settings since its gpu:N indices are the ones we write to,
+    // and at full verbosity it lists the fans connected to each GPU.
+    // nvidia-smi -L is the fallback when there's no X display to query.
+    let mut gpus = match run_host_command("nvidia-settings", &["-q", "gpus", "-V", "all"]) {
+        Ok(output) => parse_settings_gpus(&output),
+        Err(e) => {
+            log::warn!("nvidia-settings -q gpus failed, trying nvidia-smi: {}", e);
+            parse_smi_gpus(&run_host_command("nvidia-smi", &["-L"])?)
+        }
+    };
+
+    if gpus.is_empty() {
+        anyhow::bail!("No NVIDIA GPUs found");
+    }
+
+    if gpus.iter().all(|gpu| gpu.fans.is_empty()) {
+        // Usually fails too when nvidia-settings couldn't list the GPUs
+        match list_fans() {
+            Ok(fans) => assign_unmapped_fans(&mut gpus, fans),
+            Err(e) => log::warn!("Could not list fans, leaving them under driver control: {}", e),
+        }
+    }
+
+    // nvidia-smi knows the PCI address for each UUID
+    let query = ["--query-gpu=uuid,pci.bus_id", "--format=csv,noheader"];
+    match run_host_command("nvidia-smi", &query) {
+        Ok(output) => assign_pci_bus_ids(&mut gpus, &output),
+        Err(e) => log::warn!("Could not read PCI bus IDs from nvidia-smi: {}", e),
+    }
+
+    Ok(gpus)
 }
 
-pub fn count_fans(_gpu_id: u32) -> Result<u32> {
-    // run: nvidia-settings -q fans
-    // output detects: [fan:0], [fan:1]...
-    
-    // Alternatively query `[gpu:0]/TargetFanSpeed` entries?
-    // Reliable way: `nvidia-settings -q consumers` or listing targets.
-    
-    // Let's try `nvidia-settings -q fans`
-    let output = run_host_command("nvidia-settings", &["-q", "fans"])?;
-    
-    // Count occurrences of "fan:N" identifiers in the output
-    // Output format often has lines starting with properties.
-    // We can count unique IDs.
-    
-    // A more robust way might be checking for `[fan:0]`, `[fan:1]` presence.
-    // Let's count unique `\[fan:(\d+)\]` matches.
-    
-    let re = Regex::new(r"\[fan:(\d+)\]").unwrap();
-    let mut max_fan_id = -1;
-    
-    for cap in re.captures_iter(&output) {
-        if let Some(m) = cap.get(1) {
-            if let Ok(id) = m.as_str().parse::<i32>() {
-                if id > max_fan_id {
-                    max_fan_id = id;
-                }
+/// Fill in PCI bus IDs from `nvidia-smi --query-gpu=uuid,pci.bus_id --format=csv,noheader`
+/// Sample line: "GPU-5d1f0c4e-..., 00000000:01:00.0"
+fn assign_pci_bus_ids(gpus: &mut [Gpu], output: &str) {
+    for line in output.lines() {
+        let Some((uuid, bus_id)) = line.split_once(',') else { continue };
+        let (uuid, bus_id) = (uuid.trim(), bus_id.trim());
+        for gpu in gpus.iter_mut() {
+            if gpu.uuid.as_deref().is_some_and(|u| u.eq_ignore_ascii_case(uuid)) {
+                gpu.pci_bus_id = Some(bus_id.to_string());
             }
         }
     }
-    
-    if max_fan_id >= 0 {
-        Ok((max_fan_id + 1) as u32)
-    } else {
-        Ok(0)
+}
+
+/// Parse "[domain:]bus:device.function" (hex) so differently padded IDs compare equal
+pub fn parse_pci_bus_id(s: &str) -> Option<(u32, u32, u32, u32)> {
+    let (rest, function) = s.trim().rsplit_once('.')?;
+    let mut parts: Vec<&str> = rest.split(':').collect();
+    let device = parts.pop()?;
+    let bus = parts.pop()?;
+    let domain = parts.pop().unwrap_or("0");
+    if !parts.is_empty() {
+        return None;
     }
+
+    let hex = |p: &str| u32::from_str_radix(p, 16).ok();
+    Some((hex(domain)?, hex(bus)?, hex(device)?, hex(function)?))
 }
 
-pub fn get_gpu_name(gpu_id: u32) -> Result<String> {
-    // nvidia-settings -q gpus
-    // output: "  [0] hostname:0[gpu:0] (NVIDIA GeForce RTX 3080)"
-    let output = run_host_command("nvidia-settings", &["-q", "gpus"])?;
-    
-    // Simple regex to capture content inside parentheses
-    // This assumes the last parentheses pair contains the name
-    let re = Regex::new(r"\(([^)]+)\)$").unwrap();
-    
+/// Parse `nvidia-settings -q gpus -V all`
+/// Sample line: "    [0] my_machine:0[gpu:0] (NVIDIA GeForce RTX 3080)"
+/// followed by the GPU's names (one of them the UUID) and its connections,
+/// including lines like "        [fan:0] (Fan 0)"
+fn parse_settings_gpus(output: &str) -> Vec<Gpu> {
+    let gpu_re = Regex::new(r"\[gpu:(\d+)\]\s*\((.+)\)\s*$").unwrap();
+    let fan_re = Regex::new(r"\[fan:(\d+)\]").unwrap();
+    let uuid_re = Regex::new(r"^\s*(GPU-[0-9a-fA-F]{8}(?:-[0-9a-fA-F]+)+)\s*$").unwrap();
+    let mut gpus: Vec<Gpu> = Vec::new();
+
     for line in output.lines() {
-        if line.contains(&format!("[gpu:{}]", gpu_id)) {
-            if let Some(cap) = re.captures(line) {
-                if let Some(name) = cap.get(1) {
-                    return Ok(name.as_str().to_string());
+        if let Some(cap) = gpu_re.captures(line) {
+            if let Ok(id) = cap[1].parse() {
+                gpus.push(Gpu { id, name: cap[2].to_string(), ..Default::default() });
+            }
+            continue;
+        }
+
+        // Every fan mentioned inside a GPU's block belongs to that GPU
+        if let Some(gpu) = gpus.last_mut() {
+            if let Some(cap) = uuid_re.captures(line) {
+                gpu.uuid = Some(cap[1].to_string());
+            }
+            for cap in fan_re.captures_iter(line) {
+                if let Ok(fan) = cap[1].parse() {
+                    if !gpu.fans.contains(&fan) {
+                        gpu.fans.push(fan);
+                    }
                 }
             }
         }
     }
-    
-    Ok(format!("Nvidia GPU {}", gpu_id))
+
+    gpus
+}
+
+/// Parse `nvidia-smi -L`
+/// Sample line: "GPU 0: NVIDIA GeForce RTX 3080 (UUID: GPU-5d1f...)"
+/// nvidia-smi numbers GPUs in PCI order, nvidia-settings by NV-CONTROL target,
+/// so with several cards the index says nothing about `[gpu:N]`.
+fn parse_smi_gpus(output: &str) -> Vec<Gpu> {
+    let re = Regex::new(r"^GPU (\d+): (.+?)(?: \(UUID: ([^)]+)\))?\s*$").unwrap();
+    let mut gpus: Vec<Gpu> = output
+        .lines()
+        .filter_map(|line| {
+            let cap = re.captures(line.trim())?;
+            Some(Gpu {
+                id: cap[1].parse().ok()?,
+                name: cap[2].to_string(),
+                uuid: cap.get(3).map(|m| m.as_str().to_string()),
+                ..Default::default()
+            })
+        })
+        .collect();
+
+    // A lone GPU is gpu:0 either way
+    if gpus.len() > 1 {
+        for gpu in &mut gpus {
+            gpu.unmapped = true;
+        }
+    }
+    gpus
+}
+
+/// Fallback when the driver didn't tell us which fan belongs to which GPU.
+/// With a single GPU every fan is its own; with several we refuse to guess,
+/// so those cards stay under the driver's automatic control.
+fn assign_unmapped_fans(gpus: &mut [Gpu], fans: Vec<u32>) {
+    if fans.is_empty() {
+        return;
+    }
+
+    if let [gpu] = gpus {
+        gpu.fans = fans;
+    } else {
+        log::warn!(
+            "Could not map {} fan(s) to {} GPUs; leaving them under driver control",
+            fans.len(),
+            gpus.len()
+        );
+    }
+}
+
+/// List every `[fan:N]` target on the system
+pub fn list_fans() -> Result<Vec<u32>> {
+    let output = run_host_command("nvidia-settings", &["-q", "fans"])?;
+    Ok(parse_fans(&output))
+}
+
+fn parse_fans(output: &str) -> Vec<u32> {
+    let re = Regex::new(r"\[fan:(\d+)\]").unwrap();
+    let mut fans: Vec<u32> = re
+        .captures_iter(output)
+        .filter_map(|cap| cap[1].parse().ok())
+        .collect();
+    fans.sort_unstable();
+    fans.dedup();
+    fans
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_settings_gpus() {
+        let output = "
+2 GPUs on render01:0
+
+    [0] render01:0[gpu:0] (NVIDIA GeForce RTX 3090)
+
+      Has the following names:
+        GPU-0
+        GPU-5d1f0c4e-1111-2222-3333-444455556666
+
+      Is connected to the following fans:
+        [fan:0] (Fan 0)
+        [fan:1] (Fan 1)
+
+    [1] render01:0[gpu:1] (NVIDIA RTX A4000)
+
+      Has the following names:
+        GPU-1
+        GPU-9a8b7c6d-1111-2222-3333-444455556666
+
+      Is connected to the following fans:
+        [fan:2] (Fan 2)
+";
+        let gpus = parse_settings_gpus(output);
+        assert_eq!(gpus.len(), 2);
+        assert_eq!((gpus[0].id, gpus[0].name.as_str()), (0, "NVIDIA GeForce RTX 3090"));
+        assert_eq!((gpus[1].id, gpus[1].name.as_str()), (1, "NVIDIA RTX A4000"));
+        assert_eq!(gpus[0].fans, vec![0, 1]);
+        assert_eq!(gpus[1].fans, vec![2]);
+        assert_eq!(gpus[0].uuid.as_deref(), Some("GPU-5d1f0c4e-1111-2222-3333-444455556666"));
+        assert_eq!(gpus[1].uuid.as_deref(), Some("GPU-9a8b7c6d-1111-2222-3333-444455556666"));
+    }
+
+    #[test]
+    fn test_parse_smi_gpus() {
+        let output = "GPU 0: NVIDIA GeForce RTX 3090 (UUID: GPU-5d1f0c4e-1111-2222-3333-444455556666)
+GPU 1: NVIDIA RTX A4000 (UUID: GPU-9a8b7c6d-1111-2222-3333-444455556666)
+";
+        let gpus = parse_smi_gpus(output);
+        assert_eq!(gpus.len(), 2);
+        assert_eq!((gpus[0].id, gpus[0].name.as_str()), (0, "NVIDIA GeForce RTX 3090"));
+        assert_eq!((gpus[1].id, gpus[1].name.as_str()), (1, "NVIDIA RTX A4000"));
+        assert!(gpus[0].fans.is_empty());
+        assert_eq!(gpus[1].uuid.as_deref(), Some("GPU-9a8b7c6d-1111-2222-3333-444455556666"));
+        assert!(gpus.iter().all(|g| g.unmapped));
+
+        let single = parse_smi_gpus("GPU 0: NVIDIA GeForce RTX 3090 (UUID: GPU-5d1f0c4e-1111)\n");
+        assert!(!single[0].unmapped);
+    }
+
+    #[test]
+    fn test_assign_pci_bus_ids() {
+        let mut gpus = vec![
+            Gpu { id: 0, uuid: Some("GPU-5d1f0c4e-1111".into()), ..Default::default() },
+            Gpu { id: 1, uuid: Some("GPU-9a8b7c6d-1111".into()), ..Default::default() },
+        ];
+        // nvidia-smi may list the cards in a different order than nvidia-settings
+        let output = "GPU-9A8B7C6D-1111, 00000000:02:00.0\nGPU-5d1f0c4e-1111, 00000000:01:00.0\n";
+        assign_pci_bus_ids(&mut gpus, output);
+        assert_eq!(gpus[0].pci_bus_id.as_deref(), Some("00000000:01:00.0"));
+        assert_eq!(gpus[1].pci_bus_id.as_deref(), Some("00000000:02:00.0"));
+    }
+
+    #[test]
+    fn test_same_card() {
+        let nvml = Gpu {
+            id: 0,
+            uuid: Some("GPU-9A8B7C6D-1111".into()),
+            pci_bus_id: Some("00000000:02:00.0".into()),
+            ..Default::default()
+        };
+        let settings = |id, uuid: Option<&str>, bus_id: &str| Gpu {
+            id,
+            uuid: uuid.map(String::from),
+            pci_bus_id: Some(bus_id.into()),
+            ..Default::default()
+        };
+
+        // Index differs, the card is the same
+        assert!(nvml.same_card(&settings(1, Some("GPU-9a8b7c6d-1111"), "00000000:02:00.0")));
+        assert!(!nvml.same_card(&settings(0, Some("GPU-5d1f0c4e-1111"), "00000000:01:00.0")));
+        // No UUID on one side: the bus ID decides
+        assert!(nvml.same_card(&settings(1, None, "02:00.0")));
+        assert!(!nvml.same_card(&settings(0, None, "01:00.0")));
+    }
+
+    #[test]
+    fn test_parse_fans() {
+        let output = "
+3 Fans on render01:0
+
+    [0] render01:0[fan:0] (Fan 0)
+    [1] render01:0[fan:1] (Fan 1)
+    [2] render01:0[fan:2] (Fan 2)
+";
+        assert_eq!(parse_fans(output), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn test_unmapped_fans() {
+        let gpu = |id| Gpu { id, name: format!("GPU {}", id), ..Default::default() };
+
+        // A lone GPU owns every fan
+        let mut single = vec![gpu(0)];
+        assign_unmapped_fans(&mut single, vec![0, 1]);
+        assert_eq!(single[0].fans, vec![0, 1]);
+
+        // Several GPUs: never hand one card's fans to another
+        let mut multi = vec![gpu(0), gpu(1)];
+        assign_unmapped_fans(&mut multi, vec![0, 1, 2]);
+        assert!(multi.iter().all(|g| g.fans.is_empty()));
+    }
 }
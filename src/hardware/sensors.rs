@@ -0,0 +1,208 @@
+//! Temperature sensors outside the GPU, for curves that follow the rest of the system
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use anyhow::{anyhow, bail, Context, Result};
+use sysinfo::Components;
+
+use crate::config::{Config, SensorSource};
+
+/// Sensor name that always means the core temperature of the GPU being controlled
+pub const GPU_SENSOR: &str = "gpu";
+
+/// How deep virtual sensors may nest, which also stops reference loops
+const MAX_DEPTH: usize = 8;
+
+const HWMON_ROOT: &str = "/sys/class/hwmon";
+
+/// Labels of CPU package sensors, best match first
+const CPU_LABELS: &[&str] = &["Package id", "Tctl", "Tdie", "coretemp", "k10temp", "zenpower", "cpu"];
+
+/// Host sensor state shared by every GPU in a loop iteration
+#[derive(Default)]
+pub struct Sensors {
+    /// Only enumerated once a configured sensor needs it
+    components: Option<Components>,
+}
+
+impl Sensors {
+    /// Update the host readings; called once per loop iteration
+    pub fn refresh(&mut self, config: &Config) {
+        let needs_components = config.sensors.iter()
+            .any(|sensor| matches!(sensor.source, SensorSource::Cpu | SensorSource::Component { .. }));
+        if !needs_components {
+            self.components = None;
+            return;
+        }
+        match &mut self.components {
+            Some(components) => components.refresh(),
+            None => self.components = Some(Components::new_with_refreshed_list()),
+        }
+    }
+
+    /// Current value of a named sensor. `gpu_temp` stands in for the `gpu` sensor.
+    pub fn read(&self, name: &str, config: &Config, gpu_temp: u32) -> Result<f64> {
+        self.read_at(name, config, gpu_temp, 0)
+    }
+
+    fn read_at(&self, name: &str, config: &Config, gpu_temp: u32, depth: usize) -> Result<f64> {
+        if name == GPU_SENSOR {
+            return Ok(gpu_temp as f64);
+        }
+        if depth >= MAX_DEPTH {
+            bail!("sensor {:?} nests too deeply (is there a loop?)", name);
+        }
+        let sensor = config.sensors.iter()
+            .find(|sensor| sensor.name == name)
+            .ok_or_else(|| anyhow!("no sensor named {:?}", name))?;
+        let read = |name: &str| self.read_at(name, config, gpu_temp, depth + 1);
+
+        match &sensor.source {
+            SensorSource::Cpu => self.cpu_temp(),
+            SensorSource::Component { label } => self.component_temp(label),
+            SensorSource::Hwmon { chip, sensor } => read_hwmon(Path::new(HWMON_ROOT), chip, sensor),
+            SensorSource::File { path, scale } => Ok(read_number(path)? * scale),
+            SensorSource::Max { sensors } => {
+                let values = sensors.iter().map(|name| read(name)).collect::<Result<Vec<_>>>()?;
+                values.into_iter().reduce(f64::max).ok_or_else(|| anyhow!("sensor {:?} combines no sensors", name))
+            }
+            SensorSource::Average { sensors } => {
+                let values = sensors.iter().map(|name| read(name)).collect::<Result<Vec<_>>>()?;
+                if values.is_empty() {
+                    bail!("sensor {:?} combines no sensors", name);
+                }
+                Ok(values.iter().sum::<f64>() / values.len() as f64)
+            }
+            SensorSource::Difference { a, b } => Ok(read(a)? - read(b)?),
+        }
+    }
+
+    fn components(&self) -> Result<&Components> {
+        self.components.as_ref().ok_or_else(|| anyhow!("system sensors not loaded"))
+    }
+
+    /// Hottest sensor of the best-matching CPU label
+    fn cpu_temp(&self) -> Result<f64> {
+        let components = self.components()?;
+        CPU_LABELS.iter()
+            .find_map(|pattern| max_temp(components, pattern))
+            .ok_or_else(|| anyhow!("no CPU temperature sensor found"))
+    }
+
+    fn component_temp(&self, label: &str) -> Result<f64> {
+        max_temp(self.components()?, label).ok_or_else(|| anyhow!("no sensor labelled {:?}", label))
+    }
+}
+
+/// Highest temperature among components whose label contains `pattern`
+fn max_temp(components: &Components, pattern: &str) -> Option<f64> {
+    let pattern = pattern.to_lowercase();
+    components.iter()
+        .filter(|component| component.label().to_lowercase().contains(&pattern))
+        .map(|component| component.temperature() as f64)
+        .filter(|temp| temp.is_finite() && *temp > 0.0)
+        .reduce(f64::max)
+}
+
+/// First number in a file
+fn read_number(path: &Path) -> Result<f64> {
+    let content = fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+    content
+        .split_whitespace()
+        .next()
+        .and_then(|word| word.parse().ok())
+        .ok_or_else(|| anyhow!("{} does not start with a number", path.display()))
+}
+
+/// A hwmon temperature by chip name and either its label ("Tctl") or input name ("temp1")
+fn read_hwmon(root: &Path, chip: &str, sensor: &str) -> Result<f64> {
+    for entry in fs::read_dir(root).with_context(|| format!("reading {}", root.display()))? {
+        let dir = entry?.path();
+        let name = fs::read_to_string(dir.join("name")).unwrap_or_default();
+        if name.trim() != chip {
+            continue;
+        }
+        let input = hwmon_input(&dir, sensor)
+            .ok_or_else(|| anyhow!("hwmon chip {:?} has no sensor {:?}", chip, sensor))?;
+        // hwmon reports millidegrees
+        return Ok(read_number(&input)? / 1000.0);
+    }
+    bail!("no hwmon chip named {:?}", chip)
+}
+
+fn hwmon_input(dir: &Path, sensor: &str) -> Option<PathBuf> {
+    let direct = dir.join(format!("{}_input", sensor));
+    if direct.exists() {
+        return Some(direct);
+    }
+    fs::read_dir(dir).ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .find(|path| {
+            path.file_name().and_then(|f| f.to_str()).is_some_and(|f| f.starts_with("temp") && f.ends_with("_label"))
+                && fs::read_to_string(path).is_ok_and(|label| label.trim() == sensor)
+        })
+        .map(|label| PathBuf::from(label.to_string_lossy().replace("_label", "_input")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("nvidia-wormhole-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_hwmon_lookup() {
+        let root = scratch_dir("hwmon");
+        for (dir, name) in [("hwmon0", "nvme"), ("hwmon3", "k10temp")] {
+            fs::create_dir(root.join(dir)).unwrap();
+            fs::write(root.join(dir).join("name"), format!("{}\n", name)).unwrap();
+        }
+        fs::write(root.join("hwmon0/temp1_input"), "38850\n").unwrap();
+        fs::write(root.join("hwmon3/temp1_input"), "61250\n").unwrap();
+        fs::write(root.join("hwmon3/temp3_input"), "55000\n").unwrap();
+        fs::write(root.join("hwmon3/temp3_label"), "Tccd1\n").unwrap();
+
+        assert_eq!(read_hwmon(&root, "k10temp", "temp1").unwrap(), 61.25);
+        assert_eq!(read_hwmon(&root, "k10temp", "Tccd1").unwrap(), 55.0);
+        assert_eq!(read_hwmon(&root, "nvme", "temp1").unwrap(), 38.85);
+        assert!(read_hwmon(&root, "k10temp", "Tccd2").is_err());
+        assert!(read_hwmon(&root, "coretemp", "temp1").is_err());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_file_and_virtual_sensors() {
+        let dir = scratch_dir("sensors");
+        fs::write(dir.join("ambient"), "24.5\n").unwrap();
+        fs::write(dir.join("millis"), "71000 extra words").unwrap();
+        let config: Config = serde_json::from_str(&format!(r#"{{
+            "curve": [[30, 0]],
+            "interval_ms": 2000,
+            "sensors": [
+                {{ "name": "ambient", "type": "file", "path": "{0}/ambient" }},
+                {{ "name": "water", "type": "file", "path": "{0}/millis", "scale": 0.001 }},
+                {{ "name": "hottest", "type": "max", "sensors": ["gpu", "water"] }},
+                {{ "name": "mean", "type": "average", "sensors": ["ambient", "water"] }},
+                {{ "name": "delta", "type": "difference", "a": "hottest", "b": "ambient" }},
+                {{ "name": "loop", "type": "max", "sensors": ["loop"] }}
+            ]
+        }}"#, dir.display())).unwrap();
+
+        let sensors = Sensors::default();
+        assert_eq!(sensors.read("ambient", &config, 60).unwrap(), 24.5);
+        assert_eq!(sensors.read("water", &config, 60).unwrap(), 71.0);
+        assert_eq!(sensors.read("hottest", &config, 60).unwrap(), 71.0);
+        assert_eq!(sensors.read("hottest", &config, 80).unwrap(), 80.0);
+        assert_eq!(sensors.read("mean", &config, 60).unwrap(), 47.75);
+        assert_eq!(sensors.read("delta", &config, 80).unwrap(), 55.5);
+        assert!(sensors.read("loop", &config, 60).is_err());
+        assert!(sensors.read("nope", &config, 60).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
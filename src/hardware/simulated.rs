@@ -0,0 +1,401 @@
+//! Virtual GPU backend for `--simulate`
+//! A simple thermal model: load heats the card, fans and passive loss cool it towards ambient
+
+use std::sync::Mutex;
+use std::time::Instant;
+use anyhow::{Result, bail};
+
+use crate::config::SimulationConfig;
+use super::backend::GpuBackend;
+use super::probe::Gpu;
+
+/// Heating in °C/s at idle and at full load with no cooling
+const IDLE_HEAT: f64 = 0.3;
+const LOAD_HEAT: f64 = 1.7;
+/// Cooling per second, as a fraction of the distance to ambient
+const PASSIVE_COOLING: f64 = 0.02;
+const FAN_COOLING: f64 = 0.05;
+/// Largest step used when integrating the model
+const MAX_STEP_SECS: f64 = 0.1;
+/// Board power at full load; a lower limit scales the load heat down
+const DEFAULT_POWER_LIMIT: u32 = 250;
+const IDLE_POWER: f64 = 25.0;
+/// Fan speed at 100% duty
+const MAX_RPM: u32 = 3000;
+/// A stopped fan needs this much duty to get going, a spinning one keeps turning down to the stop duty
+const START_DUTY: u32 = 25;
+const STOP_DUTY: u32 = 15;
+/// Below this a fan counts as stopped
+const SPINNING_RPM: f64 = 100.0;
+/// Time constant of a fan settling to a new speed, in seconds
+const FAN_LAG_SECS: f64 = 1.0;
+/// How much hotter than the core the memory runs at full load
+const MEMORY_LOAD_OFFSET: f64 = 12.0;
+
+struct SimGpu {
+    temp: f64,
+    fan_speeds: Vec<u32>,
+    /// What each fan is actually spinning at
+    fan_rpms: Vec<f64>,
+    /// Fans were set by us; otherwise the virtual driver runs them
+    manual: bool,
+    power_limit: u32,
+}
+
+struct SimState {
+    gpus: Vec<SimGpu>,
+    /// Simulated seconds since start, drives the load profile
+    elapsed: f64,
+    last_sync: Instant,
+}
+
+/// Backend that models one or more virtual GPUs in software
+pub struct Simulated {
+    config: SimulationConfig,
+    /// Follow the wall clock on every read; disabled for deterministic tests
+    realtime: bool,
+    state: Mutex<SimState>,
+}
+
+impl Simulated {
+    pub fn new(config: SimulationConfig) -> Self {
+        let gpus = (0..config.gpus.max(1))
+            .map(|_| SimGpu {
+                temp: config.ambient_temp,
+                fan_speeds: vec![0; config.fans_per_gpu as usize],
+                fan_rpms: vec![0.0; config.fans_per_gpu as usize],
+                manual: false,
+                power_limit: DEFAULT_POWER_LIMIT,
+            })
+            .collect();
+
+        Self {
+            config,
+            realtime: true,
+            state: Mutex::new(SimState {
+                gpus,
+                elapsed: 0.0,
+                last_sync: Instant::now(),
+            }),
+        }
+    }
+
+    /// Same model, but time only moves through `advance`
+    #[cfg(test)]
+    pub fn manual(config: SimulationConfig) -> Self {
+        Self { realtime: false, ..Self::new(config) }
+    }
+
+    /// Run the thermal model forward by `secs` simulated seconds
+    #[cfg(test)]
+    pub fn advance(&self, secs: f64) {
+        let mut state = self.state.lock().unwrap();
+        self.step(&mut state, secs);
+    }
+
+    /// Whether the given GPU's fans are currently under our control
+    #[cfg(test)]
+    pub fn manual_control(&self, gpu_id: u32) -> bool {
+        self.state.lock().unwrap().gpus[gpu_id as usize].manual
+    }
+
+    fn sync(&self) -> std::sync::MutexGuard<'_, SimState> {
+        let mut state = self.state.lock().unwrap();
+        if self.realtime {
+            let now = Instant::now();
+            let secs = now.duration_since(state.last_sync).as_secs_f64();
+            state.last_sync = now;
+            self.step(&mut state, secs);
+        }
+        state
+    }
+
+    fn step(&self, state: &mut SimState, mut secs: f64) {
+        while secs > 0.0 {
+            let dt = secs.min(MAX_STEP_SECS);
+            let load = self.load_at(state.elapsed) as f64 / 100.0;
+            let ambient = self.config.ambient_temp;
+
+            for gpu in &mut state.gpus {
+                if !gpu.manual {
+                    let speed = driver_speed(gpu.temp);
+                    gpu.fan_speeds.iter_mut().for_each(|fan| *fan = speed);
+                }
+                for (rpm, &duty) in gpu.fan_rpms.iter_mut().zip(&gpu.fan_speeds) {
+                    *rpm += (settled_rpm(duty, *rpm) - *rpm) * (dt / FAN_LAG_SECS).min(1.0);
+                }
+
+                let fan = if gpu.fan_speeds.is_empty() {
+                    0.0
+                } else {
+                    gpu.fan_speeds.iter().sum::<u32>() as f64 / gpu.fan_speeds.len() as f64 / 100.0
+                };
+                let power = (gpu.power_limit as f64 / DEFAULT_POWER_LIMIT as f64).min(1.0);
+                let heat = IDLE_HEAT + LOAD_HEAT * load * power;
+                let cooling = (PASSIVE_COOLING + FAN_COOLING * fan) * (gpu.temp - ambient);
+                gpu.temp += (heat - cooling) * dt;
+            }
+
+            state.elapsed += dt;
+            secs -= dt;
+        }
+    }
+
+    /// GPU usage from the load profile at the given time
+    fn load_at(&self, elapsed: f64) -> u32 {
+        let profile = &self.config.load_profile;
+        let period: u64 = profile.iter().map(|(secs, _)| secs).sum();
+        if period == 0 {
+            return profile.first().map(|(_, load)| *load).unwrap_or(0);
+        }
+
+        let mut t = elapsed % period as f64;
+        for &(secs, load) in profile {
+            if t < secs as f64 {
+                return load;
+            }
+            t -= secs as f64;
+        }
+        0
+    }
+
+    fn check_gpu(&self, gpu_id: u32) -> Result<usize> {
+        if gpu_id >= self.config.gpus.max(1) {
+            bail!("No simulated GPU {}", gpu_id);
+        }
+        Ok(gpu_id as usize)
+    }
+}
+
+impl GpuBackend for Simulated {
+    fn name(&self) -> &'static str {
+        "simulated"
+    }
+
+    fn detect_gpus(&self) -> Result<Vec<Gpu>> {
+        Ok((0..self.config.gpus.max(1))
+            .map(|id| Gpu {
+                id,
+                name: format!("Simulated GPU {}", id),
+                uuid: Some(format!("GPU-00000000-0000-0000-0000-{:012}", id)),
+                pci_bus_id: Some(format!("00000000:{:02x}:00.0", id + 1)),
+                fans: (0..self.config.fans_per_gpu).collect(),
+                ..Default::default()
+            })
+            .collect())
+    }
+
+    fn get_temp(&self, gpu_id: u32) -> Result<u32> {
+        let idx = self.check_gpu(gpu_id)?;
+        let state = self.sync();
+        Ok(state.gpus[idx].temp.round().max(0.0) as u32)
+    }
+
+    fn get_gpu_usage(&self, gpu: &Gpu) -> Result<u32> {
+        self.check_gpu(gpu.id)?;
+        let state = self.sync();
+        Ok(self.load_at(state.elapsed))
+    }
+
+    fn get_power_draw(&self, gpu: &Gpu) -> Result<u32> {
+        let idx = self.check_gpu(gpu.id)?;
+        let state = self.sync();
+        let load = self.load_at(state.elapsed) as f64 / 100.0;
+        let limit = state.gpus[idx].power_limit.min(DEFAULT_POWER_LIMIT) as f64;
+        Ok((IDLE_POWER + (limit - IDLE_POWER).max(0.0) * load).round() as u32)
+    }
+
+    fn get_memory_temp(&self, gpu: &Gpu) -> Result<u32> {
+        let idx = self.check_gpu(gpu.id)?;
+        let state = self.sync();
+        let load = self.load_at(state.elapsed) as f64 / 100.0;
+        Ok((state.gpus[idx].temp + MEMORY_LOAD_OFFSET * load).round().max(0.0) as u32)
+    }
+
+    fn fan_speed_range(&self, gpu_id: u32) -> Result<(u32, u32)> {
+        self.check_gpu(gpu_id)?;
+        Ok((self.config.min_fan_speed.min(100), 100))
+    }
+
+    fn get_fan_speed(&self, gpu_id: u32, fan_id: u32) -> Result<u32> {
+        let idx = self.check_gpu(gpu_id)?;
+        let state = self.sync();
+        state.gpus[idx]
+            .fan_speeds
+            .get(fan_id as usize)
+            .copied()
+            .ok_or_else(|| anyhow::anyhow!("No fan {} on simulated GPU {}", fan_id, gpu_id))
+    }
+
+    fn get_fan_rpm(&self, gpu_id: u32, fan_id: u32) -> Result<u32> {
+        let idx = self.check_gpu(gpu_id)?;
+        let state = self.sync();
+        let rpm = state.gpus[idx]
+            .fan_rpms
+            .get(fan_id as usize)
+            .ok_or_else(|| anyhow::anyhow!("No fan {} on simulated GPU {}", fan_id, gpu_id))?;
+        Ok(if *rpm < SPINNING_RPM { 0 } else { rpm.round() as u32 })
+    }
+
+    fn set_fan_speed(&self, gpu_id: u32, fan_id: u32, speed: u32) -> Result<()> {
+        let idx = self.check_gpu(gpu_id)?;
+        let mut state = self.sync();
+        let fan = state.gpus[idx]
+            .fan_speeds
+            .get_mut(fan_id as usize)
+            .ok_or_else(|| anyhow::anyhow!("No fan {} on simulated GPU {}", fan_id, gpu_id))?;
+        // Like the driver, quietly raise speeds below the card's minimum
+        *fan = speed.clamp(self.config.min_fan_speed.min(100), 100);
+        state.gpus[idx].manual = true;
+        Ok(())
+    }
+
+    fn restore_auto(&self, gpu: &Gpu) -> Result<()> {
+        let idx = self.check_gpu(gpu.id)?;
+        let mut state = self.sync();
+        state.gpus[idx].manual = false;
+        Ok(())
+    }
+
+    fn set_power_limit(&self, gpu: &Gpu, watts: Option<u32>) -> Result<()> {
+        let idx = self.check_gpu(gpu.id)?;
+        let mut state = self.sync();
+        state.gpus[idx].power_limit = watts.unwrap_or(DEFAULT_POWER_LIMIT);
+        Ok(())
+    }
+}
+
+/// Speed a fan heads towards at a duty, given how fast it turns now
+fn settled_rpm(duty: u32, rpm: f64) -> f64 {
+    let threshold = if rpm >= SPINNING_RPM { STOP_DUTY } else { START_DUTY };
+    if duty < threshold {
+        0.0
+    } else {
+        (duty * MAX_RPM / 100) as f64
+    }
+}
+
+/// Stand-in for the driver's own fan logic: zero RPM when cool, then a ramp from 30%
+fn driver_speed(temp: f64) -> u32 {
+    if temp < 50.0 {
+        0
+    } else {
+        (30.0 + (temp - 50.0) * 2.0).min(100.0) as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(load: u32) -> SimulationConfig {
+        SimulationConfig {
+            load_profile: vec![(60, load)],
+            ..SimulationConfig::default()
+        }
+    }
+
+    fn set_all_fans(sim: &Simulated, speed: u32) {
+        for fan in 0..2 {
+            sim.set_fan_speed(0, fan, speed).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_load_heats_and_fans_cool() {
+        let idle = Simulated::manual(config(0));
+        let busy = Simulated::manual(config(100));
+        set_all_fans(&idle, 0);
+        set_all_fans(&busy, 0);
+        idle.advance(300.0);
+        busy.advance(300.0);
+        assert!(busy.get_temp(0).unwrap() > idle.get_temp(0).unwrap() + 30);
+
+        let cooled = Simulated::manual(config(100));
+        set_all_fans(&cooled, 100);
+        cooled.advance(300.0);
+        assert!(cooled.get_temp(0).unwrap() + 20 < busy.get_temp(0).unwrap());
+    }
+
+    #[test]
+    fn test_restore_auto_hands_fans_to_driver() {
+        let sim = Simulated::manual(config(100));
+        set_all_fans(&sim, 0);
+        sim.advance(300.0);
+        let stuck = sim.get_temp(0).unwrap();
+
+        let gpu = sim.detect_gpus().unwrap().remove(0);
+        sim.restore_auto(&gpu).unwrap();
+        sim.advance(300.0);
+        assert!(sim.get_temp(0).unwrap() + 20 < stuck);
+    }
+
+    #[test]
+    fn test_power_limit_reduces_heat() {
+        let full = Simulated::manual(config(100));
+        let limited = Simulated::manual(config(100));
+        set_all_fans(&full, 0);
+        set_all_fans(&limited, 0);
+        let gpu = limited.detect_gpus().unwrap().remove(0);
+        limited.set_power_limit(&gpu, Some(DEFAULT_POWER_LIMIT / 2)).unwrap();
+        full.advance(300.0);
+        limited.advance(300.0);
+        assert!(limited.get_temp(0).unwrap() + 10 < full.get_temp(0).unwrap());
+    }
+
+    #[test]
+    fn test_load_profile_repeats() {
+        let sim = Simulated::manual(SimulationConfig {
+            load_profile: vec![(10, 5), (20, 90)],
+            ..SimulationConfig::default()
+        });
+        assert_eq!(sim.load_at(0.0), 5);
+        assert_eq!(sim.load_at(15.0), 90);
+        assert_eq!(sim.load_at(35.0), 5);
+        let gpu = sim.detect_gpus().unwrap().remove(0);
+        assert_eq!(sim.get_gpu_usage(&gpu).unwrap(), 5);
+    }
+
+    #[test]
+    fn test_rejects_unknown_targets() {
+        let sim = Simulated::manual(SimulationConfig::default());
+        assert!(sim.get_temp(1).is_err());
+        assert!(sim.set_fan_speed(0, 5, 50).is_err());
+    }
+
+    #[test]
+    fn test_minimum_fan_speed() {
+        let sim = Simulated::manual(SimulationConfig { min_fan_speed: 30, ..config(0) });
+        assert_eq!(sim.fan_speed_range(0).unwrap(), (30, 100));
+
+        // Writes below the minimum run at the minimum, as on a real card
+        let stopped = Simulated::manual(config(0));
+        set_all_fans(&sim, 0);
+        set_all_fans(&stopped, 30);
+        sim.advance(120.0);
+        stopped.advance(120.0);
+        assert_eq!(sim.get_temp(0).unwrap(), stopped.get_temp(0).unwrap());
+    }
+
+    #[test]
+    fn test_fans_need_a_push_to_start() {
+        let sim = Simulated::manual(config(0));
+        set_all_fans(&sim, 20);
+        sim.advance(10.0);
+        assert_eq!(sim.get_fan_rpm(0, 0).unwrap(), 0);
+
+        // Spins up with a lag, then keeps going at a duty it could not start from
+        set_all_fans(&sim, START_DUTY);
+        sim.advance(0.5);
+        assert!(sim.get_fan_rpm(0, 0).unwrap() < 750);
+        sim.advance(10.0);
+        assert_eq!(sim.get_fan_rpm(0, 0).unwrap(), 750);
+        set_all_fans(&sim, 20);
+        sim.advance(10.0);
+        assert_eq!(sim.get_fan_rpm(0, 0).unwrap(), 600);
+
+        set_all_fans(&sim, STOP_DUTY - 1);
+        sim.advance(10.0);
+        assert_eq!(sim.get_fan_rpm(0, 0).unwrap(), 0);
+    }
+}
@@ -5,44 +5,86 @@ mod config;
 mod setup;
 mod util;
 
-use config::Config;
+use daemon::calibrate;
 use daemon::r#loop::DaemonState;
+use daemon::signals::{self, ShutdownSignals};
+use daemon::supervisor;
 use gui::app;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
 
 fn main() {
     env_logger::init();
     
     let args: Vec<String> = std::env::args().collect();
     let daemon_mode = args.contains(&String::from("--daemon"));
+    let simulate = args.contains(&String::from("--simulate"));
+    let supervised = args.contains(&String::from(supervisor::SUPERVISED_FLAG));
     
-    if daemon_mode {
-        run_daemon();
+    if args.contains(&String::from("--restore-fans")) {
+        let backend = hardware::select_backend(&supervisor::load_config(), simulate);
+        std::process::exit(if supervisor::restore_fans(&*backend) { 0 } else { 1 });
+    } else if args.contains(&String::from("--calibrate")) {
+        std::process::exit(calibrate::run(simulate));
+    } else if daemon_mode && supervised {
+        std::process::exit(run_daemon(simulate));
+    } else if daemon_mode {
+        std::process::exit(supervisor::run(simulate));
     } else {
-        if let Err(e) = app::run() {
+        if let Err(e) = app::run(simulate) {
             log::error!("GUI error: {}", e);
         }
     }
 }
 
-fn run_daemon() {
+/// The control process started by the supervisor; returns the exit code
+fn run_daemon(simulate: bool) -> i32 {
     log::info!("Starting nvidia-wormhole in daemon mode");
+    supervisor::exit_with_parent();
     
     // Load config
-    let config = Config::load().unwrap_or_else(|e| {
-        log::warn!("Failed to load config: {}, using defaults", e);
-        Config::default()
-    });
+    let config = supervisor::load_config();
     
     log::info!("Loaded curve: {:?}", config.curve);
     
+    // Block shutdown signals before any thread is spawned so only sigwait sees them
+    let shutdown = match ShutdownSignals::block() {
+        Ok(shutdown) => shutdown,
+        Err(e) => {
+            log::error!("Failed to set up signal handling: {}", e);
+            return 1;
+        }
+    };
+    
     // Start daemon
-    let daemon = DaemonState::new(config.curve);
-    daemon.start(config.interval_ms);
+    let backend = hardware::select_backend(&config, simulate);
+    let interval_ms = config.interval_ms;
+    let daemon = DaemonState::new(config, backend);
+    daemon.start(interval_ms);
     
     log::info!("Daemon running. Press Ctrl+C to stop.");
     
-    // Keep main thread alive
-    loop {
-        std::thread::sleep(std::time::Duration::from_secs(60));
-    }
+    // Keep main thread alive until asked to stop, or until the control loop dies
+    let code = loop {
+        match shutdown.wait_timeout(Duration::from_secs(1)) {
+            Ok(Some(sig)) => {
+                log::info!("Received {}, shutting down", signals::name(sig));
+                break 0;
+            }
+            Ok(None) if !daemon.running.load(Ordering::Relaxed) => {
+                log::error!("Control loop stopped unexpectedly, exiting");
+                break 1;
+            }
+            Ok(None) => {}
+            Err(e) => {
+                log::error!("{}, shutting down", e);
+                break 1;
+            }
+        }
+    };
+    
+    // Joins the worker, which hands the fans back to the driver
+    daemon.stop();
+    log::info!("Shutdown complete");
+    code
 }
@@ -0,0 +1,146 @@
+/*
+ * Minimal stand-in for libnvidia-ml.so.1 used by the NVML backend tests.
+ * Two GPUs with two fans each, and a passively cooled compute card that reports
+ * neither a name nor fans. Device handles are just index + 1.
+ */
+#include <stdio.h>
+#include <string.h>
+
+#define NVML_SUCCESS 0
+#define NVML_ERROR_INVALID_ARGUMENT 2
+#define NVML_ERROR_NOT_SUPPORTED 3
+#define GPUS 3
+#define PASSIVE_GPU 2
+#define FANS 2
+
+typedef void *nvmlDevice_t;
+typedef struct { unsigned int gpu; unsigned int memory; } nvmlUtilization_t;
+typedef struct {
+    char busIdLegacy[16];
+    unsigned int domain, bus, device, pciDeviceId, pciSubSystemId;
+    char busId[32];
+} nvmlPciInfo_t;
+
+static const unsigned int temps[GPUS] = { 45, 60, 50 };
+static const unsigned int usage[GPUS] = { 10, 80, 0 };
+static const unsigned int power_mw[GPUS] = { 35000, 149600, 70000 };
+static const unsigned int min_fan[GPUS] = { 30, 0, 0 };
+/* -1 means the driver is in charge */
+static int fan_speeds[GPUS][FANS] = { { -1, -1 }, { -1, -1 }, { -1, -1 } };
+
+static int index_of(nvmlDevice_t device) {
+    long idx = (long)device - 1;
+    return (idx >= 0 && idx < GPUS) ? (int)idx : -1;
+}
+
+int nvmlInit_v2(void) { return NVML_SUCCESS; }
+int nvmlShutdown(void) { return NVML_SUCCESS; }
+
+const char *nvmlErrorString(int result) {
+    switch (result) {
+    case NVML_ERROR_INVALID_ARGUMENT: return "Invalid Argument";
+    case NVML_ERROR_NOT_SUPPORTED: return "Not Supported";
+    default: return "Unknown Error";
+    }
+}
+
+int nvmlDeviceGetCount_v2(unsigned int *count) {
+    *count = GPUS;
+    return NVML_SUCCESS;
+}
+
+int nvmlDeviceGetHandleByIndex_v2(unsigned int index, nvmlDevice_t *device) {
+    if (index >= GPUS) return NVML_ERROR_INVALID_ARGUMENT;
+    *device = (nvmlDevice_t)(long)(index + 1);
+    return NVML_SUCCESS;
+}
+
+int nvmlDeviceGetName(nvmlDevice_t device, char *name, unsigned int length) {
+    int idx = index_of(device);
+    if (idx < 0) return NVML_ERROR_INVALID_ARGUMENT;
+    if (idx == PASSIVE_GPU) return NVML_ERROR_NOT_SUPPORTED;
+    snprintf(name, length, "Stub GPU %d", idx);
+    return NVML_SUCCESS;
+}
+
+int nvmlDeviceGetUUID(nvmlDevice_t device, char *uuid, unsigned int length) {
+    int idx = index_of(device);
+    if (idx < 0) return NVML_ERROR_INVALID_ARGUMENT;
+    snprintf(uuid, length, "GPU-00000000-0000-0000-0000-%012d", idx);
+    return NVML_SUCCESS;
+}
+
+int nvmlDeviceGetPciInfo_v3(nvmlDevice_t device, nvmlPciInfo_t *pci) {
+    int idx = index_of(device);
+    if (idx < 0) return NVML_ERROR_INVALID_ARGUMENT;
+    memset(pci, 0, sizeof(*pci));
+    pci->bus = (unsigned int)idx + 1;
+    snprintf(pci->busId, sizeof(pci->busId), "00000000:%02x:00.0", pci->bus);
+    return NVML_SUCCESS;
+}
+
+int nvmlDeviceGetTemperature(nvmlDevice_t device, unsigned int sensor, unsigned int *temp) {
+    int idx = index_of(device);
+    if (idx < 0 || sensor != 0) return NVML_ERROR_INVALID_ARGUMENT;
+    *temp = temps[idx];
+    return NVML_SUCCESS;
+}
+
+int nvmlDeviceGetUtilizationRates(nvmlDevice_t device, nvmlUtilization_t *util) {
+    int idx = index_of(device);
+    if (idx < 0) return NVML_ERROR_INVALID_ARGUMENT;
+    util->gpu = usage[idx];
+    util->memory = usage[idx] / 2;
+    return NVML_SUCCESS;
+}
+
+int nvmlDeviceGetPowerUsage(nvmlDevice_t device, unsigned int *power) {
+    int idx = index_of(device);
+    if (idx < 0) return NVML_ERROR_INVALID_ARGUMENT;
+    *power = power_mw[idx];
+    return NVML_SUCCESS;
+}
+
+int nvmlDeviceGetMinMaxFanSpeed(nvmlDevice_t device, unsigned int *min, unsigned int *max) {
+    int idx = index_of(device);
+    if (idx < 0) return NVML_ERROR_INVALID_ARGUMENT;
+    *min = min_fan[idx];
+    *max = 100;
+    return NVML_SUCCESS;
+}
+
+int nvmlDeviceGetNumFans(nvmlDevice_t device, unsigned int *count) {
+    int idx = index_of(device);
+    if (idx < 0) return NVML_ERROR_INVALID_ARGUMENT;
+    if (idx == PASSIVE_GPU) return NVML_ERROR_NOT_SUPPORTED;
+    *count = FANS;
+    return NVML_SUCCESS;
+}
+
+/* What the fans run at: our setting, or 35% under the driver */
+int nvmlDeviceGetFanSpeed_v2(nvmlDevice_t device, unsigned int fan, unsigned int *speed) {
+    int idx = index_of(device);
+    if (idx < 0 || fan >= FANS) return NVML_ERROR_INVALID_ARGUMENT;
+    *speed = fan_speeds[idx][fan] < 0 ? 35 : (unsigned int)fan_speeds[idx][fan];
+    return NVML_SUCCESS;
+}
+
+int nvmlDeviceSetFanSpeed_v2(nvmlDevice_t device, unsigned int fan, unsigned int speed) {
+    int idx = index_of(device);
+    if (idx < 0 || fan >= FANS || speed > 100) return NVML_ERROR_INVALID_ARGUMENT;
+    fan_speeds[idx][fan] = (int)speed;
+    return NVML_SUCCESS;
+}
+
+int nvmlDeviceSetDefaultFanSpeed_v2(nvmlDevice_t device, unsigned int fan) {
+    int idx = index_of(device);
+    if (idx < 0 || fan >= FANS) return NVML_ERROR_INVALID_ARGUMENT;
+    fan_speeds[idx][fan] = -1;
+    return NVML_SUCCESS;
+}
+
+/* Test helper, not part of NVML */
+int stub_fan_speed(unsigned int gpu, unsigned int fan) {
+    if (gpu >= GPUS || fan >= FANS) return -2;
+    return fan_speeds[gpu][fan];
+}